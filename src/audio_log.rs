@@ -0,0 +1,495 @@
+//! The log of host calls that built the audio graph.
+//!
+//! Audio nodes are trait objects that cannot be inspected or serialized.
+//! Instead, the runtime records all calls that changed the graph, so that
+//! save states can rebuild the same graph by repeating them on a new manager.
+//! Only the structure and parameters of the graph are restored: the playback
+//! position of files and the phase of oscillators start from the beginning.
+use crate::snapshot::{Reader, SnapshotError};
+use crate::state::State;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use firefly_audio::{Manager, Processor, modulators};
+use firefly_hal::Dir;
+
+/// An audio node as it was requested by the app.
+pub(crate) enum NodeKind {
+    Sine {
+        freq: f32,
+        phase: f32,
+    },
+    Square {
+        freq: f32,
+        phase: f32,
+    },
+    Sawtooth {
+        freq: f32,
+        phase: f32,
+    },
+    Triangle {
+        freq: f32,
+        phase: f32,
+    },
+    Noise {
+        seed: i32,
+    },
+    Empty,
+    Zero,
+    /// A PCM file from the app ROM.
+    File(String),
+    Mix,
+    AllForOne,
+    Gain {
+        lvl: f32,
+    },
+    Loop,
+    Concat,
+    Pan {
+        lvl: f32,
+    },
+    Mute,
+    Pause,
+    TrackPosition,
+    LowPass {
+        freq: f32,
+        q: f32,
+    },
+    HighPass {
+        freq: f32,
+        q: f32,
+    },
+    TakeLeft,
+    TakeRight,
+    Swap,
+    Clip {
+        low: f32,
+        high: f32,
+    },
+}
+
+impl NodeKind {
+    /// Make the audio processor for the node.
+    ///
+    /// Fails only for files. The error is logged.
+    pub fn build(&self, state: &mut State) -> Option<Box<dyn Processor>> {
+        use firefly_audio::*;
+        let proc: Box<dyn Processor> = match self {
+            Self::Sine { freq, phase } => Box::new(Sine::new(*freq, *phase)),
+            Self::Square { freq, phase } => Box::new(Square::new(*freq, *phase)),
+            Self::Sawtooth { freq, phase } => Box::new(Sawtooth::new(*freq, *phase)),
+            Self::Triangle { freq, phase } => Box::new(Triangle::new(*freq, *phase)),
+            Self::Noise { seed } => Box::new(Noise::new(*seed)),
+            Self::Empty => Box::new(Empty::new()),
+            Self::Zero => Box::new(Zero::new()),
+            Self::File(name) => {
+                let reader = match state.rom_dir.open_file(name) {
+                    Ok(reader) => reader,
+                    Err(err) => {
+                        state.log_error(err);
+                        return None;
+                    }
+                };
+                match Pcm::from_file(reader) {
+                    Ok(proc) => Box::new(proc),
+                    Err(err) => {
+                        state.log_error(err);
+                        return None;
+                    }
+                }
+            }
+            Self::Mix => Box::new(Mix::new()),
+            Self::AllForOne => Box::new(AllForOne::new()),
+            Self::Gain { lvl } => Box::new(Gain::new(*lvl)),
+            Self::Loop => Box::new(Loop::new()),
+            Self::Concat => Box::new(Concat::new()),
+            Self::Pan { lvl } => Box::new(Pan::new(*lvl)),
+            Self::Mute => Box::new(Mute::new()),
+            Self::Pause => Box::new(Pause::new()),
+            Self::TrackPosition => Box::new(TrackPosition::new()),
+            Self::LowPass { freq, q } => Box::new(LowHighPass::new(true, *freq, *q)),
+            Self::HighPass { freq, q } => Box::new(LowHighPass::new(false, *freq, *q)),
+            Self::TakeLeft => Box::new(TakeLeft::new()),
+            Self::TakeRight => Box::new(TakeRight::new()),
+            Self::Swap => Box::new(Swap::new()),
+            Self::Clip { low, high } => Box::new(Clip::new(*low, *high)),
+        };
+        Some(proc)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (kind, args): (u8, &[f32]) = match self {
+            Self::Sine { freq, phase } => (0, &[*freq, *phase]),
+            Self::Square { freq, phase } => (1, &[*freq, *phase]),
+            Self::Sawtooth { freq, phase } => (2, &[*freq, *phase]),
+            Self::Triangle { freq, phase } => (3, &[*freq, *phase]),
+            Self::Noise { seed } => {
+                buf.push(4);
+                buf.extend_from_slice(&seed.to_le_bytes());
+                return;
+            }
+            Self::Empty => (5, &[]),
+            Self::Zero => (6, &[]),
+            Self::File(name) => {
+                buf.push(7);
+                buf.push(name.len() as u8);
+                buf.extend_from_slice(name.as_bytes());
+                return;
+            }
+            Self::Mix => (8, &[]),
+            Self::AllForOne => (9, &[]),
+            Self::Gain { lvl } => (10, &[*lvl]),
+            Self::Loop => (11, &[]),
+            Self::Concat => (12, &[]),
+            Self::Pan { lvl } => (13, &[*lvl]),
+            Self::Mute => (14, &[]),
+            Self::Pause => (15, &[]),
+            Self::TrackPosition => (16, &[]),
+            Self::LowPass { freq, q } => (17, &[*freq, *q]),
+            Self::HighPass { freq, q } => (18, &[*freq, *q]),
+            Self::TakeLeft => (19, &[]),
+            Self::TakeRight => (20, &[]),
+            Self::Swap => (21, &[]),
+            Self::Clip { low, high } => (22, &[*low, *high]),
+        };
+        buf.push(kind);
+        for arg in args {
+            buf.extend_from_slice(&arg.to_le_bytes());
+        }
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, SnapshotError> {
+        let node = match reader.u8()? {
+            0 => Self::Sine {
+                freq: reader.f32()?,
+                phase: reader.f32()?,
+            },
+            1 => Self::Square {
+                freq: reader.f32()?,
+                phase: reader.f32()?,
+            },
+            2 => Self::Sawtooth {
+                freq: reader.f32()?,
+                phase: reader.f32()?,
+            },
+            3 => Self::Triangle {
+                freq: reader.f32()?,
+                phase: reader.f32()?,
+            },
+            4 => Self::Noise {
+                seed: reader.u32()? as i32,
+            },
+            5 => Self::Empty,
+            6 => Self::Zero,
+            7 => {
+                let len = reader.u8()?;
+                let name = reader.take(usize::from(len))?;
+                let Ok(name) = core::str::from_utf8(name) else {
+                    return Err(SnapshotError::Invalid);
+                };
+                Self::File(String::from(name))
+            }
+            8 => Self::Mix,
+            9 => Self::AllForOne,
+            10 => Self::Gain { lvl: reader.f32()? },
+            11 => Self::Loop,
+            12 => Self::Concat,
+            13 => Self::Pan { lvl: reader.f32()? },
+            14 => Self::Mute,
+            15 => Self::Pause,
+            16 => Self::TrackPosition,
+            17 => Self::LowPass {
+                freq: reader.f32()?,
+                q: reader.f32()?,
+            },
+            18 => Self::HighPass {
+                freq: reader.f32()?,
+                q: reader.f32()?,
+            },
+            19 => Self::TakeLeft,
+            20 => Self::TakeRight,
+            21 => Self::Swap,
+            22 => Self::Clip {
+                low: reader.f32()?,
+                high: reader.f32()?,
+            },
+            _ => return Err(SnapshotError::Invalid),
+        };
+        Ok(node)
+    }
+}
+
+/// A modulator as it was requested by the app.
+#[derive(Clone, Copy)]
+pub(crate) enum Lfo {
+    Linear {
+        start_at: u32,
+        end_at: u32,
+    },
+    Hold {
+        time: u32,
+    },
+    Adsr {
+        attack: u32,
+        decay: u32,
+        sustain: u32,
+        sustain_level: f32,
+        release: u32,
+    },
+    Sine {
+        freq: f32,
+    },
+    Square {
+        period: u32,
+    },
+    Sawtooth {
+        period: u32,
+    },
+}
+
+impl Lfo {
+    pub fn build(&self) -> Box<dyn modulators::Modulator> {
+        match *self {
+            Self::Linear { start_at, end_at } => {
+                Box::new(modulators::Linear::new(start_at, end_at))
+            }
+            Self::Hold { time } => Box::new(modulators::Hold::new(time)),
+            Self::Adsr {
+                attack,
+                decay,
+                sustain,
+                sustain_level,
+                release,
+            } => Box::new(modulators::Adsr::new(
+                attack,
+                decay,
+                sustain,
+                sustain_level,
+                release,
+            )),
+            Self::Sine { freq } => Box::new(modulators::Sine::new(freq)),
+            Self::Square { period } => Box::new(modulators::Pulse::new_square(period)),
+            Self::Sawtooth { period } => Box::new(modulators::Triangle::new_sawtooth(period)),
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        // Float arguments are stored as their bits.
+        let (kind, args): (u8, &[u32]) = match *self {
+            Self::Linear { start_at, end_at } => (0, &[start_at, end_at]),
+            Self::Hold { time } => (1, &[time]),
+            Self::Adsr {
+                attack,
+                decay,
+                sustain,
+                sustain_level,
+                release,
+            } => (
+                2,
+                &[attack, decay, sustain, sustain_level.to_bits(), release],
+            ),
+            Self::Sine { freq } => (3, &[freq.to_bits()]),
+            Self::Square { period } => (4, &[period]),
+            Self::Sawtooth { period } => (5, &[period]),
+        };
+        buf.push(kind);
+        for arg in args {
+            buf.extend_from_slice(&arg.to_le_bytes());
+        }
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, SnapshotError> {
+        let lfo = match reader.u8()? {
+            0 => Self::Linear {
+                start_at: reader.u32()?,
+                end_at: reader.u32()?,
+            },
+            1 => Self::Hold {
+                time: reader.u32()?,
+            },
+            2 => Self::Adsr {
+                attack: reader.u32()?,
+                decay: reader.u32()?,
+                sustain: reader.u32()?,
+                sustain_level: reader.f32()?,
+                release: reader.u32()?,
+            },
+            3 => Self::Sine {
+                freq: reader.f32()?,
+            },
+            4 => Self::Square {
+                period: reader.u32()?,
+            },
+            5 => Self::Sawtooth {
+                period: reader.u32()?,
+            },
+            _ => return Err(SnapshotError::Invalid),
+        };
+        Ok(lfo)
+    }
+}
+
+/// A successful host call that changed the audio graph.
+pub(crate) enum AudioCall {
+    Add {
+        parent: u32,
+        node: NodeKind,
+    },
+    Set {
+        node: u32,
+        param: u8,
+        val: f32,
+    },
+    Modulate {
+        node: u32,
+        param: u8,
+        lfo: Lfo,
+        low: f32,
+        high: f32,
+    },
+    Clear {
+        node: u32,
+    },
+}
+
+#[derive(Default)]
+pub(crate) struct AudioLog {
+    calls: Vec<AudioCall>,
+}
+
+impl AudioLog {
+    /// Record the call.
+    ///
+    /// Only the last value and the last modulator of each param matter,
+    /// so the earlier ones are dropped to keep the log from growing
+    /// when the app changes params on every frame.
+    /// Added nodes are always kept because node IDs depend on them.
+    pub fn push(&mut self, call: AudioCall) {
+        let replaces = |old: &AudioCall| match (old, &call) {
+            (
+                AudioCall::Set { node, param, .. },
+                AudioCall::Set {
+                    node: new_node,
+                    param: new_param,
+                    ..
+                },
+            )
+            | (
+                AudioCall::Modulate { node, param, .. },
+                AudioCall::Modulate {
+                    node: new_node,
+                    param: new_param,
+                    ..
+                },
+            ) => node == new_node && param == new_param,
+            _ => false,
+        };
+        self.calls.retain(|old| !replaces(old));
+        self.calls.push(call);
+    }
+
+    /// Build a new audio graph by repeating all recorded calls.
+    ///
+    /// Returns None if any of the calls fails, which can happen only if
+    /// a file from the log is missing or changed.
+    pub fn replay(&self, state: &mut State) -> Option<Manager> {
+        let mut audio = Manager::new();
+        for call in &self.calls {
+            match call {
+                AudioCall::Add { parent, node } => {
+                    let proc = node.build(state)?;
+                    audio.add_node(*parent, proc).ok()?;
+                }
+                AudioCall::Set { node, param, val } => {
+                    audio.get_node(*node).ok()?.set(*param, *val);
+                }
+                AudioCall::Modulate {
+                    node,
+                    param,
+                    lfo,
+                    low,
+                    high,
+                } => {
+                    let lfo = lfo.build();
+                    audio
+                        .get_node(*node)
+                        .ok()?
+                        .modulate(*param, lfo, *low, *high);
+                }
+                AudioCall::Clear { node } => {
+                    audio.clear(*node).ok()?;
+                }
+            }
+        }
+        Some(audio)
+    }
+
+    /// Serialize the log: the number of calls (u32) followed by the calls.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.calls.len() as u32).to_le_bytes());
+        for call in &self.calls {
+            match call {
+                AudioCall::Add { parent, node } => {
+                    buf.push(0);
+                    buf.extend_from_slice(&parent.to_le_bytes());
+                    node.encode(buf);
+                }
+                AudioCall::Set { node, param, val } => {
+                    buf.push(1);
+                    buf.extend_from_slice(&node.to_le_bytes());
+                    buf.push(*param);
+                    buf.extend_from_slice(&val.to_le_bytes());
+                }
+                AudioCall::Modulate {
+                    node,
+                    param,
+                    lfo,
+                    low,
+                    high,
+                } => {
+                    buf.push(2);
+                    buf.extend_from_slice(&node.to_le_bytes());
+                    buf.push(*param);
+                    buf.extend_from_slice(&low.to_le_bytes());
+                    buf.extend_from_slice(&high.to_le_bytes());
+                    lfo.encode(buf);
+                }
+                AudioCall::Clear { node } => {
+                    buf.push(3);
+                    buf.extend_from_slice(&node.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    pub fn decode(reader: &mut Reader<'_>) -> Result<Self, SnapshotError> {
+        let n_calls = reader.u32()?;
+        let mut calls = Vec::new();
+        for _ in 0..n_calls {
+            let call = match reader.u8()? {
+                0 => AudioCall::Add {
+                    parent: reader.u32()?,
+                    node: NodeKind::decode(reader)?,
+                },
+                1 => AudioCall::Set {
+                    node: reader.u32()?,
+                    param: reader.u8()?,
+                    val: reader.f32()?,
+                },
+                2 => AudioCall::Modulate {
+                    node: reader.u32()?,
+                    param: reader.u8()?,
+                    low: reader.f32()?,
+                    high: reader.f32()?,
+                    lfo: Lfo::decode(reader)?,
+                },
+                3 => AudioCall::Clear {
+                    node: reader.u32()?,
+                },
+                _ => return Err(SnapshotError::Invalid),
+            };
+            calls.push(call);
+        }
+        Ok(Self { calls })
+    }
+}
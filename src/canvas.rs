@@ -1,9 +1,14 @@
+use crate::error::HostError;
+use crate::image::PackedTarget;
+use crate::pattern::PatternTarget;
 use crate::state::State;
 use alloc::boxed::Box;
 use core::convert::Infallible;
+use core::ops::Range;
 use embedded_graphics::Pixel;
 use embedded_graphics::pixelcolor::Gray4;
 use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
 
 const PPB: usize = 2;
 
@@ -24,19 +29,117 @@ impl Canvas {
         }
     }
 
+    /// The address, size, and width of the canvas, as passed to [`Canvas::new`].
+    pub fn raw(&self) -> (u32, u32, u32) {
+        let size = self.end - self.start;
+        (self.start as u32, size as u32, self.width as u32)
+    }
+
     /// Make a draw target that modifies the data inside the canvas.
     pub fn as_target<'a>(&self, caller: &'a mut wasmi::Caller<'_, Box<State>>) -> CanvasBuffer<'a> {
         let state = caller.data();
+        let clip = state.clip;
         // safety: memory presence is ensured in set_canvas
         let memory = state.memory.unwrap();
         let memory = memory.data_mut(caller);
         let data = &mut memory[self.start..self.end];
-        let height = data.len() * 2 / self.width;
-        CanvasBuffer {
-            data,
-            width: self.width,
-            height,
+        CanvasBuffer::new(data, self.width, clip)
+    }
+
+    /// Get the color of the canvas pixel at the given point.
+    ///
+    /// Returns [`None`] if the point is out of the canvas bounds.
+    pub fn get_pixel(&self, data: &[u8], point: Point) -> Option<u8> {
+        let data = data.get(self.start..self.end)?;
+        let height = data.len() * PPB / self.width;
+        // Negative values will be wrapped and filtered out.
+        let x = point.x as usize;
+        let y = point.y as usize;
+        if x >= self.width || y >= height {
+            return None;
         }
+        let pixel_index = y * self.width + x;
+        let byte = data[pixel_index / PPB];
+        let luma = if pixel_index.is_multiple_of(2) {
+            byte & 0xf
+        } else {
+            byte >> 4
+        };
+        Some(luma)
+    }
+
+    /// Split the guest memory into the canvas draw target and the rest of the memory.
+    ///
+    /// Used when drawing on the canvas requires reading other data
+    /// (like fonts) from the guest memory at the same time.
+    pub fn split<'a>(
+        &self,
+        data: &'a mut [u8],
+        clip: Option<Rectangle>,
+    ) -> (CanvasBuffer<'a>, MemoryView<'a>) {
+        let (before, rest) = data.split_at_mut(self.start);
+        let (data, after) = rest.split_at_mut(self.end - self.start);
+        let target = CanvasBuffer::new(data, self.width, clip);
+        let view = MemoryView {
+            before,
+            after,
+            after_start: self.end,
+        };
+        (target, view)
+    }
+}
+
+/// Split the guest memory into the canvas draw target (if any) and the rest of the memory.
+pub fn split_memory<'a>(
+    canvas: Option<&Canvas>,
+    clip: Option<Rectangle>,
+    data: &'a mut [u8],
+) -> (Option<CanvasBuffer<'a>>, MemoryView<'a>) {
+    match canvas {
+        Some(canvas) => {
+            let (target, view) = canvas.split(data, clip);
+            (Some(target), view)
+        }
+        None => (None, MemoryView::new(data)),
+    }
+}
+
+/// Read-only view of the guest memory with the canvas region cut out.
+pub struct MemoryView<'a> {
+    before: &'a [u8],
+    after: &'a [u8],
+    after_start: usize,
+}
+
+impl<'a> MemoryView<'a> {
+    /// View the whole memory.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            before: data,
+            after: &[],
+            after_start: data.len(),
+        }
+    }
+
+    /// Get the memory region of the given size starting at the given address.
+    ///
+    /// Fails if the region is out of memory or overlaps with the canvas.
+    pub fn get(&self, start: usize, len: usize) -> Result<&'a [u8], HostError> {
+        let Some(end) = start.checked_add(len) else {
+            return Err(HostError::OomPointer);
+        };
+        if end <= self.before.len() {
+            return Ok(&self.before[start..end]);
+        }
+        if start >= self.after_start {
+            let start = start - self.after_start;
+            let end = end - self.after_start;
+            return self.after.get(start..end).ok_or(HostError::OomPointer);
+        }
+        if end <= self.after_start + self.after.len() {
+            return Err(HostError::CanvasOverlap);
+        }
+        Err(HostError::OomPointer)
     }
 }
 
@@ -47,6 +150,8 @@ pub struct CanvasBuffer<'a> {
     data: &'a mut [u8],
     width: usize,
     height: usize,
+    /// The area that can be drawn on. The whole canvas if no clipping is set.
+    clip: Rectangle,
 }
 
 impl OriginDimensions for CanvasBuffer<'_> {
@@ -73,7 +178,52 @@ impl DrawTarget for CanvasBuffer<'_> {
     }
 }
 
-impl CanvasBuffer<'_> {
+impl PackedTarget for CanvasBuffer<'_> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    fn set_pixel(&mut self, point: Point, luma: u8) {
+        Self::set_pixel(self, Pixel(point, Gray4::new(luma)));
+    }
+
+    // The canvas is a part of the guest memory, there is nothing to flush.
+    fn mark_rows_dirty(&mut self, _rows: Range<usize>) {}
+
+    fn is_clipped(&self) -> bool {
+        self.clip != self.bounding_box()
+    }
+
+    fn clip(&self) -> Rectangle {
+        self.clip
+    }
+}
+
+impl PatternTarget for CanvasBuffer<'_> {}
+
+impl<'a> CanvasBuffer<'a> {
+    fn new(data: &'a mut [u8], width: usize, clip: Option<Rectangle>) -> Self {
+        let height = data.len() * 2 / width;
+        let bounds = Rectangle::new(Point::zero(), Size::new(width as u32, height as u32));
+        let clip = match clip {
+            Some(clip) => clip.intersection(&bounds),
+            None => bounds,
+        };
+        Self {
+            data,
+            width,
+            height,
+            clip,
+        }
+    }
     fn set_pixel(&mut self, pixel: Pixel<Gray4>) {
         let Pixel(point, color) = pixel;
         let x = point.x as usize;
@@ -81,6 +231,9 @@ impl CanvasBuffer<'_> {
         if y >= self.height || x >= self.width {
             return; // the pixel is out of bounds
         }
+        if !self.clip.contains(point) {
+            return; // the pixel is clipped
+        }
         let pixel_index = y * self.width + x;
         let byte_index = pixel_index / PPB;
         let shift = if pixel_index.is_multiple_of(2) { 0 } else { 4 };
@@ -4,6 +4,7 @@ use core::fmt;
 pub enum Error {
     Wasmi(wasmi::Error),
     FuncCall(&'static str, wasmi::Error, RuntimeStats),
+    OutOfFuel(&'static str, u64, RuntimeStats),
     FileEmpty(&'static str),
     OpenDir(alloc::string::String, firefly_hal::FSError),
     OpenFile(&'static str, firefly_hal::FSError),
@@ -28,6 +29,9 @@ pub enum Error {
 
     CheatUndefined,
     CheatInNet,
+
+    InvalidReplay,
+    InvalidFuel,
 }
 
 impl fmt::Display for Error {
@@ -61,6 +65,9 @@ impl fmt::Display for Error {
             Self::OpenFile(s, e) => write!(f, "cannot open {s} file: {e}"),
             Self::NoLauncher => write!(f, "no launcher installed"),
             Self::FuncCall(func, err, stats) => write!(f, "error calling {func}: {err}.\n{stats}"),
+            Self::OutOfFuel(func, fuel, stats) => {
+                write!(f, "{func} ran out of fuel ({fuel} instructions).\n{stats}")
+            }
             Self::InvalidAuthorID(err) => write!(f, "invalid author ID: {err}"),
             Self::InvalidAppID(err) => write!(f, "invalid app ID: {err}"),
             Self::CannotDisplay => write!(f, "failed to draw on the display"),
@@ -77,6 +84,24 @@ impl fmt::Display for Error {
             Self::SerialRecv(err) => write!(f, "cannot read from serial port: {err}"),
             Self::CheatUndefined => write!(f, "the app doesn't have cheat callback"),
             Self::CheatInNet => write!(f, "cheats are disabled in multiplayer"),
+            Self::InvalidReplay => write!(f, "the replay file is invalid"),
+            Self::InvalidFuel => write!(f, "the _fuel file is invalid"),
+        }
+    }
+}
+
+impl Error {
+    /// Wrap an error returned by a guest callback that had the given fuel budget.
+    pub(crate) fn from_call(
+        func: &'static str,
+        err: wasmi::Error,
+        fuel: u64,
+        stats: RuntimeStats,
+    ) -> Self {
+        if err.as_trap_code() == Some(wasmi::TrapCode::OutOfFuel) {
+            Self::OutOfFuel(func, fuel, stats)
+        } else {
+            Self::FuncCall(func, err, stats)
         }
     }
 }
@@ -136,6 +161,9 @@ pub(crate) enum HostError {
     NoBoards,
     NoBoard(u32),
     ValueTooBig,
+    InvalidFps(u32),
+    CanvasOverlap,
+    SizeOverflow,
 }
 
 impl fmt::Display for HostError {
@@ -166,6 +194,9 @@ impl fmt::Display for HostError {
             Self::NoBoards => write!(f, "the app doesn't have any boards"),
             Self::NoBoard(id) => write!(f, "the app doesn't have a board with ID {id}"),
             Self::ValueTooBig => write!(f, "the value is too big"),
+            Self::InvalidFps(fps) => write!(f, "unsupported frame rate: {fps}"),
+            Self::CanvasOverlap => write!(f, "buffer overlaps with the canvas"),
+            Self::SizeOverflow => write!(f, "the size is too big"),
         }
     }
 }
@@ -0,0 +1,450 @@
+use core::convert::Infallible;
+use embedded_graphics::image::ImageRaw;
+use embedded_graphics::mono_font::{DecorationDimensions, MonoFont, MonoTextStyle, mapping};
+use embedded_graphics::pixelcolor::Gray4;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+
+/// The magic number of proportional fonts with a sparse Unicode code point table.
+///
+/// Header (all numbers little-endian):
+///
+/// * magic number (u8)
+/// * line height (u8), the height of every glyph
+/// * baseline (u8), the distance from the glyph top to the baseline
+/// * number of glyphs (u16)
+/// * width of the glyph atlas in pixels (u16)
+///
+/// The header is followed by the glyph table sorted by the code point,
+/// each entry is 8 bytes:
+///
+/// * code point (u32)
+/// * X position of the glyph in the atlas (u16)
+/// * glyph width (u8)
+/// * advance (u8), the distance to the next glyph
+///
+/// The table is followed by the glyph atlas, 1 bit per pixel, the first pixel
+/// in the highest bit, each row padded to a whole byte.
+const MAGIC_UTF8: u8 = 0x12;
+
+const UTF8_HEADER: usize = 7;
+const UTF8_ENTRY: usize = 8;
+
+/// Horizontal alignment of lines in a text box.
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+pub enum Font<'a> {
+    Mono(MonoFont<'a>),
+    Utf8(Utf8Font<'a>),
+}
+
+impl<'a> Font<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        match bytes.first() {
+            Some(&MAGIC_UTF8) => Ok(Self::Utf8(Utf8Font::parse(bytes)?)),
+            _ => Ok(Self::Mono(parse_mono(bytes)?)),
+        }
+    }
+
+    /// The distance between baselines of two lines of text.
+    pub fn line_height(&self) -> u32 {
+        match self {
+            Self::Mono(font) => font.character_size.height,
+            Self::Utf8(font) => font.line_height,
+        }
+    }
+
+    /// The distance from the top of a line to its baseline.
+    pub fn baseline(&self) -> u32 {
+        match self {
+            Self::Mono(font) => font.baseline,
+            Self::Utf8(font) => font.baseline,
+        }
+    }
+
+    /// The horizontal distance from the start of the glyph to the start of the next one.
+    pub fn advance(&self, ch: char) -> u32 {
+        match self {
+            Self::Mono(font) => font.character_size.width + font.character_spacing,
+            Self::Utf8(font) => font.glyph(ch).map_or(0, |g| g.advance),
+        }
+    }
+
+    /// Draw a single character with the top-left corner at the given point.
+    pub fn draw_char<D>(&self, ch: char, point: Point, color: Gray4, target: &mut D)
+    where
+        D: DrawTarget<Color = Gray4, Error = Infallible>,
+    {
+        match self {
+            Self::Mono(font) => {
+                let mut buf = [0u8; 4];
+                let style = MonoTextStyle::new(font, color);
+                let text =
+                    Text::with_baseline(ch.encode_utf8(&mut buf), point, style, Baseline::Top);
+                _ = text.draw(target);
+            }
+            Self::Utf8(font) => {
+                if let Some(glyph) = font.glyph(ch) {
+                    font.draw_glyph(&glyph, point, color, target);
+                }
+            }
+        }
+    }
+
+    /// Draw the text with the baseline of the first line at the given point.
+    pub fn draw<D>(&self, text: &str, point: Point, color: Gray4, target: &mut D)
+    where
+        D: DrawTarget<Color = Gray4, Error = Infallible>,
+    {
+        match self {
+            Self::Mono(font) => {
+                let style = MonoTextStyle::new(font, color);
+                _ = Text::new(text, point, style).draw(target);
+            }
+            Self::Utf8(_) => {
+                let line_height = self.line_height() as i32;
+                let mut top_left = point - Point::new(0, self.baseline() as i32);
+                for line in text.split('\n') {
+                    let mut p = top_left;
+                    for ch in line.chars() {
+                        self.draw_char(ch, p, color, target);
+                        p.x += self.advance(ch) as i32;
+                    }
+                    top_left.y += line_height;
+                }
+            }
+        }
+    }
+
+    /// The size of the area covered by the text if it was drawn with this font.
+    pub fn measure(&self, text: &str) -> Size {
+        match self {
+            Self::Mono(font) => {
+                let style = MonoTextStyle::new(font, Gray4::BLACK);
+                Text::new(text, Point::zero(), style).bounding_box().size
+            }
+            Self::Utf8(font) => {
+                if text.is_empty() {
+                    return Size::zero();
+                }
+                let mut width = 0;
+                let mut lines = 0;
+                for line in text.split('\n') {
+                    let line_width: u32 = line.chars().map(|ch| self.advance(ch)).sum();
+                    width = width.max(line_width);
+                    lines += 1;
+                }
+                Size::new(width, lines * font.line_height)
+            }
+        }
+    }
+
+    /// Draw the text wrapped to fit into the given area.
+    ///
+    /// Lines that don't fully fit into the area height are not drawn.
+    /// Returns the number of characters drawn, including spaces and line breaks.
+    pub fn draw_box<D>(
+        &self,
+        text: &str,
+        area: Rectangle,
+        align: Align,
+        line_spacing: u32,
+        char_spacing: u32,
+        color: Gray4,
+        target: &mut D,
+    ) -> usize
+    where
+        D: DrawTarget<Color = Gray4, Error = Infallible>,
+    {
+        let line_height = self.line_height();
+        let mut y = area.top_left.y;
+        let mut bottom = line_height;
+        let mut n_chars = 0;
+        for line in self.wrap(text, area.size.width, char_spacing) {
+            if bottom > area.size.height {
+                break;
+            }
+            let free = area.size.width.saturating_sub(line.width) as i32;
+            let mut x = match align {
+                Align::Left => area.top_left.x,
+                Align::Center => area.top_left.x + free / 2,
+                Align::Right => area.top_left.x + free,
+            };
+            for ch in line.text.chars() {
+                self.draw_char(ch, Point::new(x, y), color, target);
+                x += (self.advance(ch) + char_spacing) as i32;
+            }
+            n_chars += line.n_chars;
+            y += (line_height + line_spacing) as i32;
+            bottom += line_height + line_spacing;
+        }
+        n_chars
+    }
+
+    /// Split the text into lines that fit into the given width.
+    ///
+    /// Lines are broken at spaces and newlines. Words that don't fit
+    /// into a single line are broken at any character.
+    pub fn wrap<'t>(&'t self, text: &'t str, max_width: u32, spacing: u32) -> Wrap<'t> {
+        Wrap {
+            font: self,
+            rest: text,
+            max_width,
+            spacing,
+        }
+    }
+}
+
+/// A single line of the wrapped text.
+pub struct Line<'t> {
+    /// The text of the line without the line break and trailing spaces.
+    pub text: &'t str,
+    /// The width of the line in pixels.
+    pub width: u32,
+    /// The number of characters consumed from the text, including the line break.
+    pub n_chars: usize,
+}
+
+/// Iterator over lines of the text wrapped to fit the given width.
+pub struct Wrap<'t> {
+    font: &'t Font<'t>,
+    rest: &'t str,
+    max_width: u32,
+    spacing: u32,
+}
+
+impl<'t> Wrap<'t> {
+    /// Cut the line of the given length from the text and skip the line break.
+    fn take(&mut self, end: usize, width: u32, resume: usize) -> Line<'t> {
+        let n_chars = self.rest[..resume].chars().count();
+        // Trailing spaces are invisible, so they are excluded from the width
+        // and don't shift the line when it is aligned to the right or center.
+        let text = self.rest[..end].trim_end_matches(' ');
+        let n_spaces = (end - text.len()) as u32;
+        let space = self.font.advance(' ').saturating_add(self.spacing);
+        let width = width.saturating_sub(n_spaces.saturating_mul(space));
+        self.rest = &self.rest[resume..];
+        // There is no spacing after the last character.
+        let width = if text.is_empty() {
+            0
+        } else {
+            width.saturating_sub(self.spacing)
+        };
+        Line {
+            text,
+            width,
+            n_chars,
+        }
+    }
+}
+
+impl<'t> Iterator for Wrap<'t> {
+    type Item = Line<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let mut width = 0;
+        // The last space where the line can be broken: its position
+        // and the line width before it.
+        let mut space = None;
+        for (i, ch) in self.rest.char_indices() {
+            if ch == '\n' {
+                return Some(self.take(i, width, i + 1));
+            }
+            if ch == ' ' {
+                space = Some((i, width));
+            }
+            let advance = self.font.advance(ch);
+            // Spaces at the end of the line are allowed to overflow.
+            // The spacing after the last character of the line doesn't count.
+            if ch != ' ' && i > 0 && width.saturating_add(advance) > self.max_width {
+                return Some(match space {
+                    Some((end, width)) => self.take(end, width, end + 1),
+                    None => self.take(i, width, i),
+                });
+            }
+            width = width.saturating_add(advance.saturating_add(self.spacing));
+        }
+        Some(self.take(self.rest.len(), width, self.rest.len()))
+    }
+}
+
+/// A proportional font with glyphs for arbitrary Unicode code points.
+pub struct Utf8Font<'a> {
+    line_height: u32,
+    baseline: u32,
+    /// Glyph table entries, sorted by the code point.
+    table: &'a [u8],
+    /// The glyph atlas, 1 bit per pixel.
+    atlas: &'a [u8],
+    /// The number of bytes in a single row of the atlas.
+    stride: usize,
+}
+
+struct Glyph {
+    x: u32,
+    width: u32,
+    advance: u32,
+}
+
+impl<'a> Utf8Font<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        if bytes.len() < UTF8_HEADER {
+            return Err("font is too short");
+        }
+        let line_height = u32::from(bytes[1]);
+        let baseline = u32::from(bytes[2]);
+        let n_glyphs = usize::from(read_u16(bytes, 3));
+        let atlas_width = u32::from(read_u16(bytes, 5));
+        let stride = atlas_width.div_ceil(8) as usize;
+
+        let table_end = UTF8_HEADER + n_glyphs * UTF8_ENTRY;
+        let atlas_end = table_end + stride * line_height as usize;
+        if bytes.len() < atlas_end {
+            return Err("font is too short");
+        }
+        let font = Self {
+            line_height,
+            baseline,
+            table: &bytes[UTF8_HEADER..table_end],
+            atlas: &bytes[table_end..atlas_end],
+            stride,
+        };
+
+        // Validate the table once, so that drawing never goes out of bounds.
+        let mut prev = None;
+        for entry in font.table.chunks_exact(UTF8_ENTRY) {
+            let code = read_u32(entry, 0);
+            if prev.is_some_and(|prev| prev >= code) {
+                return Err("font glyphs are not sorted by code point");
+            }
+            prev = Some(code);
+            let glyph = read_glyph(entry);
+            if glyph.x + glyph.width > atlas_width {
+                return Err("font glyph is out of the atlas bounds");
+            }
+        }
+        Ok(font)
+    }
+
+    /// Find the glyph for the given character.
+    ///
+    /// Characters not present in the font are replaced by "?".
+    fn glyph(&self, ch: char) -> Option<Glyph> {
+        self.find(u32::from(ch))
+            .or_else(|| self.find(u32::from('?')))
+    }
+
+    fn find(&self, code: u32) -> Option<Glyph> {
+        let n_glyphs = self.table.len() / UTF8_ENTRY;
+        let mut low = 0;
+        let mut high = n_glyphs;
+        while low < high {
+            let mid = (low + high) / 2;
+            let entry = &self.table[mid * UTF8_ENTRY..(mid + 1) * UTF8_ENTRY];
+            let mid_code = read_u32(entry, 0);
+            match mid_code.cmp(&code) {
+                core::cmp::Ordering::Less => low = mid + 1,
+                core::cmp::Ordering::Greater => high = mid,
+                core::cmp::Ordering::Equal => return Some(read_glyph(entry)),
+            }
+        }
+        None
+    }
+
+    fn draw_glyph<D>(&self, glyph: &Glyph, point: Point, color: Gray4, target: &mut D)
+    where
+        D: DrawTarget<Color = Gray4, Error = Infallible>,
+    {
+        let pixels = (0..self.line_height).flat_map(move |gy| {
+            (0..glyph.width).filter_map(move |gx| {
+                let ax = (glyph.x + gx) as usize;
+                let byte = self.atlas[gy as usize * self.stride + ax / 8];
+                if byte & (0b1000_0000 >> (ax % 8)) == 0 {
+                    return None;
+                }
+                let p = point + Point::new(gx as i32, gy as i32);
+                Some(Pixel(p, color))
+            })
+        });
+        _ = target.draw_iter(pixels);
+    }
+}
+
+fn read_glyph(entry: &[u8]) -> Glyph {
+    Glyph {
+        x: u32::from(read_u16(entry, 4)),
+        width: u32::from(entry[6]),
+        advance: u32::from(entry[7]),
+    }
+}
+
+/// Parse a monospaced font into the embedded-graphics font.
+fn parse_mono(bytes: &'_ [u8]) -> Result<MonoFont<'_>, &'static str> {
+    if bytes.len() < 10 {
+        let msg = if bytes.is_empty() {
+            "font is empty: make sure you load it with a correct name"
+        } else {
+            "font is too short"
+        };
+        return Err(msg);
+    }
+
+    // read the header
+    let encoding_index = read_u8(bytes, 1);
+    let char_width = u32::from(read_u8(bytes, 2));
+    let char_height = u32::from(read_u8(bytes, 3));
+    let baseline = u32::from(read_u8(bytes, 4));
+    let image_width = u32::from(read_u16(bytes, 5));
+    let image = ImageRaw::new(&bytes[7..], image_width);
+
+    let glyph_mapping: &dyn mapping::GlyphMapping = match encoding_index {
+        0x0 => &mapping::ASCII,       // ASCII
+        0x1 => &mapping::ISO_8859_1,  // Latin-1, Western European.
+        0x2 => &mapping::ISO_8859_2,  // Latin-2, Central European.
+        0x3 => &mapping::ISO_8859_3,  // Latin-3, South European.
+        0x4 => &mapping::ISO_8859_4,  // Latin-4, North European.
+        0x5 => &mapping::ISO_8859_9,  // Latin-5, Turkish.
+        0x6 => &mapping::ISO_8859_10, // Latin-6, Nordic.
+        0x7 => &mapping::ISO_8859_13, // Latin-7, Baltic Rim.
+        0x8 => &mapping::ISO_8859_14, // Latin-8, Celtic.
+        0x9 => &mapping::ISO_8859_15, // Latin-9 (revised Latin-1).
+        0xa => &mapping::ISO_8859_16, // Latin-10: South-East European.
+        0xb => &mapping::ISO_8859_5,  // Latin/Cyrillic.
+        0xc => &mapping::ISO_8859_7,  // Latin/Greek.
+        0xd => &mapping::JIS_X0201,   // Japanese katakana (halfwidth).
+        _ => return Err("unknown mapping"),
+    };
+    let font = MonoFont {
+        image,
+        character_size: Size::new(char_width, char_height),
+        character_spacing: 0,
+        baseline,
+        strikethrough: DecorationDimensions::new(char_height / 2, 1),
+        underline: DecorationDimensions::new(baseline + 2, 1),
+        glyph_mapping,
+    };
+    Ok(font)
+}
+
+/// Read little-endian u8 from the slice at the given index.
+fn read_u8(bytes: &[u8], i: usize) -> u8 {
+    bytes[i]
+}
+
+/// Read little-endian u16 from the slice at the given index.
+fn read_u16(bytes: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([bytes[i], bytes[i + 1]])
+}
+
+/// Read little-endian u32 from the slice at the given index.
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
@@ -1,7 +1,10 @@
 use crate::color::{FromRGB, Rgb16};
+use crate::image::PackedTarget;
+use crate::pattern::{Pattern, PatternTarget};
 use alloc::boxed::Box;
 use core::convert::Infallible;
 use core::marker::PhantomData;
+use core::ops::Range;
 use embedded_graphics::pixelcolor::Gray4;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
@@ -14,6 +17,10 @@ const BPP: usize = 4;
 const PPB: usize = 8 / BPP;
 /// Bytes needed to store all pixels.
 const BUFFER_SIZE: usize = WIDTH * HEIGHT / PPB;
+/// The number of u32 words needed to store one dirty bit per row.
+const DIRTY_WORDS: usize = HEIGHT.div_ceil(32);
+/// The area covering the whole screen.
+const SCREEN: Rectangle = Rectangle::new(Point::zero(), Size::new(WIDTH as u32, HEIGHT as u32));
 
 // https://lospec.com/palette-list/sweetie-16
 // https://github.com/nesbox/TIC-80/wiki/Palette
@@ -38,6 +45,11 @@ const DEFAULT_PALETTE: [Rgb16; 16] = [
 
 pub trait FireflyDisplay {
     type Error;
+    /// Show the changed parts of the frame on the screen.
+    ///
+    /// [`FrameBuffer::draw`] redraws only the changed rows. Backends that send
+    /// the pixel data directly can use [`FrameBuffer::dirty_rows`]
+    /// and then [`FrameBuffer::mark_clean`].
     fn render_fb(&mut self, frame: &mut FrameBuffer) -> Result<(), Self::Error>;
     fn rotate(&mut self, rotate: bool);
     fn set_brightness(&mut self, brightness: u8);
@@ -48,7 +60,10 @@ pub struct FrameBuffer {
     pub(crate) data: Box<[u8; BUFFER_SIZE]>,
     /// The color palette. Maps 16-color packed pixels to RGB colors.
     pub(crate) palette: [Rgb16; 16],
-    pub(crate) dirty: bool,
+    /// One bit per row, set if the row has changed since the last flush.
+    dirty: [u32; DIRTY_WORDS],
+    /// The area that can be drawn on. The whole screen if no clipping is set.
+    clip: Rectangle,
 }
 
 impl FrameBuffer {
@@ -56,10 +71,98 @@ impl FrameBuffer {
         Self {
             data: Box::new([0; BUFFER_SIZE]),
             palette: DEFAULT_PALETTE,
-            dirty: false,
+            dirty: [0; DIRTY_WORDS],
+            clip: SCREEN,
+        }
+    }
+
+    /// Check if anything has changed since the last flush.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.iter().any(|word| *word != 0)
+    }
+
+    /// Check if the given row has changed since the last flush.
+    pub fn is_row_dirty(&self, y: usize) -> bool {
+        y < HEIGHT && self.dirty[y / 32] & (1 << (y % 32)) != 0
+    }
+
+    /// Ranges of adjacent rows that have changed since the last flush.
+    ///
+    /// Display backends can use it in [`FireflyDisplay::render_fb`]
+    /// to send only the changed parts of the frame
+    /// and then call [`FrameBuffer::mark_clean`].
+    pub fn dirty_rows(&self) -> impl Iterator<Item = Range<usize>> + use<'_> {
+        let mut y = 0;
+        core::iter::from_fn(move || {
+            while y < HEIGHT && !self.is_row_dirty(y) {
+                y += 1;
+            }
+            if y >= HEIGHT {
+                return None;
+            }
+            let start = y;
+            while y < HEIGHT && self.is_row_dirty(y) {
+                y += 1;
+            }
+            Some(start..y)
+        })
+    }
+
+    /// Mark all rows as flushed on the display.
+    pub fn mark_clean(&mut self) {
+        self.dirty = [0; DIRTY_WORDS];
+    }
+
+    /// Mark the whole screen as changed.
+    pub(crate) fn mark_all_dirty(&mut self) {
+        self.mark_rows_dirty(0..HEIGHT);
+    }
+
+    /// Mark the given rows as changed. Rows outside the screen are ignored.
+    pub(crate) fn mark_rows_dirty(&mut self, rows: Range<usize>) {
+        for y in rows.start..rows.end.min(HEIGHT) {
+            self.dirty[y / 32] |= 1 << (y % 32);
         }
     }
 
+    /// Restrict all drawing to the given area. None resets it to the whole screen.
+    pub(crate) fn set_clip(&mut self, clip: Option<Rectangle>) {
+        self.clip = match clip {
+            Some(clip) => clip.intersection(&SCREEN),
+            None => SCREEN,
+        };
+    }
+
+    /// Tightly packed pixel data, 4 bits per pixel.
+    ///
+    /// Each byte holds two horizontally adjacent pixels,
+    /// the left (even) one in the low nibble.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    /// The color palette currently set by the app.
+    pub fn palette(&self) -> &[Rgb16; 16] {
+        &self.palette
+    }
+
+    /// Get the palette index of the pixel at the given coordinates.
+    ///
+    /// Returns [`None`] if the point is out of the screen bounds.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= WIDTH || y >= HEIGHT {
+            return None;
+        }
+        let index = y * WIDTH + x;
+        let byte = self.data[index / PPB];
+        let luma = if index.is_multiple_of(2) {
+            byte & 0xf
+        } else {
+            byte >> 4
+        };
+        Some(luma)
+    }
+
     pub fn iter_pairs(&self) -> impl Iterator<Item = (Rgb16, Rgb16)> + use<'_> {
         self.data.iter().map(|b| {
             let right = self.palette[usize::from(b & 0xf)];
@@ -146,7 +249,6 @@ impl DrawTarget for FrameBuffer {
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        self.dirty = true;
         for pixel in pixels {
             let Pixel(point, color) = pixel;
             self.set_pixel(point, color.luma());
@@ -155,15 +257,19 @@ impl DrawTarget for FrameBuffer {
     }
 
     fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
-        self.dirty = true;
+        if self.clip != SCREEN {
+            let clip = self.clip;
+            return self.fill_solid(&clip, color);
+        }
+        self.mark_all_dirty();
         let new_byte = color_to_byte(&color);
         self.data.fill(new_byte);
         Ok(())
     }
 
     fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
-        self.dirty = true;
         let new_byte = color_to_byte(&color);
+        let area = area.intersection(&self.clip);
 
         let left_x = area.top_left.x.clamp(0, WIDTH as _) as usize;
         let right_x = area.top_left.x + area.size.width as i32;
@@ -172,6 +278,7 @@ impl DrawTarget for FrameBuffer {
         let top_y = area.top_left.y.clamp(0, HEIGHT as _) as usize;
         let bottom_y = area.top_left.y + area.size.height as i32;
         let bottom_y = bottom_y.clamp(0, HEIGHT as _) as usize;
+        self.mark_rows_dirty(top_y..bottom_y);
 
         if right_x - left_x <= 4 {
             for x in left_x..right_x {
@@ -217,29 +324,31 @@ impl DrawTarget for FrameBuffer {
 }
 
 impl FrameBuffer {
-    /// Draw the framebuffer on an RGB screen.
+    /// Draw the changed rows of the framebuffer on an RGB screen.
     pub fn draw<D, C, E>(&mut self, target: &mut D) -> Result<(), E>
     where
         C: RgbColor + FromRGB,
         D: DrawTarget<Color = C, Error = E>,
     {
-        if !self.dirty {
-            return Ok(());
+        for rows in self.dirty_rows() {
+            let colors = ColorIter {
+                data: &self.data,
+                palette: &self.palette,
+                index: rows.start * WIDTH,
+                color: PhantomData,
+            };
+            let point = Point::new(0, rows.start as i32);
+            let size = Size::new(WIDTH as u32, rows.len() as u32);
+            let area = Rectangle::new(point, size);
+            target.fill_contiguous(&area, colors.take(rows.len() * WIDTH))?;
         }
-        self.dirty = false;
-        let colors = ColorIter {
-            data: &self.data,
-            palette: &self.palette,
-            index: 0,
-            color: PhantomData,
-        };
-        let area = Rectangle::new(Point::zero(), Size::new(WIDTH as u32, HEIGHT as u32));
-        target.fill_contiguous(&area, colors)
+        self.mark_clean();
+        Ok(())
     }
 
     /// Set color of a single pixel at the given coordinates.
     ///
-    /// Does NOT mark the buffer as dirty. This must be done by the caller.
+    /// Marks the row of the pixel as dirty.
     pub(crate) fn set_pixel(&mut self, point: Point, luma: u8) {
         // Negative values will be wrapped and filtered out
         // because any wrapped value is bigger than WIDTH/HEIGHT.
@@ -248,6 +357,9 @@ impl FrameBuffer {
         if y >= HEIGHT || x >= WIDTH {
             return; // the pixel is out of bounds
         }
+        if !self.clip.contains(point) {
+            return; // the pixel is clipped
+        }
         let pixel_index = y * WIDTH + x;
         let byte_index = pixel_index / PPB;
         let shift = if pixel_index.is_multiple_of(2) { 0 } else { 4 };
@@ -257,6 +369,70 @@ impl FrameBuffer {
         let byte = unsafe { self.data.get_unchecked_mut(byte_index) };
         debug_assert!(luma < 16);
         *byte = (luma << shift) | (*byte & mask);
+        self.dirty[y / 32] |= 1 << (y % 32);
+    }
+}
+
+impl PackedTarget for FrameBuffer {
+    fn width(&self) -> usize {
+        WIDTH
+    }
+
+    fn height(&self) -> usize {
+        HEIGHT
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..]
+    }
+
+    fn set_pixel(&mut self, point: Point, luma: u8) {
+        Self::set_pixel(self, point, luma);
+    }
+
+    fn mark_rows_dirty(&mut self, rows: Range<usize>) {
+        Self::mark_rows_dirty(self, rows);
+    }
+
+    fn is_clipped(&self) -> bool {
+        self.clip != SCREEN
+    }
+
+    fn clip(&self) -> Rectangle {
+        self.clip
+    }
+}
+
+/// Fast path for pattern fills: pairs of pixels are written as whole bytes.
+impl PatternTarget for FrameBuffer {
+    fn fill_pattern(&mut self, area: &Rectangle, pattern: &Pattern, fill: Gray4) {
+        let area = area.intersection(&self.clip);
+        let Some(bottom_right) = area.bottom_right() else {
+            return;
+        };
+        let left = area.top_left.x as usize;
+        let right = bottom_right.x as usize + 1;
+        let top = area.top_left.y as usize;
+        let bottom = bottom_right.y as usize + 1;
+        self.mark_rows_dirty(top..bottom);
+        for y in top..bottom {
+            let row = pattern.packed_row(y as i32, fill);
+            let mut x = left;
+            // An odd pixel on the edge shares the byte with a pixel outside of the area.
+            if x % 2 == 1 {
+                let point = Point::new(x as i32, y as i32);
+                self.set_pixel(point, pattern.color_at(point, fill).luma());
+                x += 1;
+            }
+            while x + 1 < right {
+                self.data[(y * WIDTH + x) / PPB] = row[(x / 2) % 2];
+                x += 2;
+            }
+            if x < right {
+                let point = Point::new(x as i32, y as i32);
+                self.set_pixel(point, pattern.color_at(point, fill).luma());
+            }
+        }
     }
 }
 
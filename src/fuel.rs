@@ -0,0 +1,130 @@
+use crate::error::Error;
+use crate::utils::read_all;
+use firefly_hal::*;
+
+/// The fuel for a callback if the app doesn't request a different budget.
+const DEFAULT_FUEL: u64 = 10_000_000;
+
+/// The biggest fuel budget an app can request for a single callback.
+const MAX_FUEL: u64 = 100_000_000;
+
+/// The smallest fuel budget an app can request for a single callback.
+///
+/// Zero in the fuel file means "use the default", anything below
+/// the minimum is raised to it to make sure the callback can do at least something.
+const MIN_FUEL: u64 = 100_000;
+
+/// The name of the optional ROM file with the app fuel budgets.
+///
+/// The file is a sequence of u64 little-endian numbers, one for each callback
+/// in the same order as the fields of [`FuelLimits`]. Trailing callbacks
+/// can be omitted and zero means the default budget.
+const FILE_NAME: &str = "_fuel";
+
+/// How much fuel each app callback can consume before it is interrupted.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct FuelLimits {
+    /// `_initialize` and `_start`.
+    pub init: u64,
+    pub boot: u64,
+    pub update: u64,
+    pub render: u64,
+    pub before_exit: u64,
+    pub cheat: u64,
+    pub handle_data: u64,
+    pub handle_menu: u64,
+}
+
+impl Default for FuelLimits {
+    fn default() -> Self {
+        Self {
+            init: DEFAULT_FUEL,
+            boot: DEFAULT_FUEL,
+            update: DEFAULT_FUEL,
+            render: DEFAULT_FUEL,
+            before_exit: DEFAULT_FUEL,
+            cheat: DEFAULT_FUEL,
+            handle_data: DEFAULT_FUEL,
+            handle_menu: DEFAULT_FUEL,
+        }
+    }
+}
+
+impl FuelLimits {
+    /// Read the fuel budgets from the app ROM.
+    ///
+    /// If the app doesn't have the fuel file, the default budgets are used.
+    pub fn load(rom_dir: &mut DirImpl) -> Result<Self, Error> {
+        let stream = match rom_dir.open_file(FILE_NAME) {
+            Ok(stream) => stream,
+            Err(FSError::NotFound) => return Ok(Self::default()),
+            Err(err) => return Err(Error::OpenFile(FILE_NAME, err)),
+        };
+        let raw = match read_all(stream) {
+            Ok(raw) => raw,
+            Err(err) => return Err(Error::ReadFile(FILE_NAME, err.into())),
+        };
+        Self::decode(&raw)
+    }
+
+    fn decode(raw: &[u8]) -> Result<Self, Error> {
+        let mut limits = Self::default();
+        if !raw.len().is_multiple_of(8) {
+            return Err(Error::InvalidFuel);
+        }
+        let fields = [
+            &mut limits.init,
+            &mut limits.boot,
+            &mut limits.update,
+            &mut limits.render,
+            &mut limits.before_exit,
+            &mut limits.cheat,
+            &mut limits.handle_data,
+            &mut limits.handle_menu,
+        ];
+        if raw.len() / 8 > fields.len() {
+            return Err(Error::InvalidFuel);
+        }
+        for (field, chunk) in fields.into_iter().zip(raw.chunks_exact(8)) {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            let fuel = u64::from_le_bytes(bytes);
+            if fuel != 0 {
+                *field = fuel.clamp(MIN_FUEL, MAX_FUEL);
+            }
+        }
+        Ok(limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut raw = alloc::vec::Vec::new();
+        raw.extend_from_slice(&0u64.to_le_bytes());
+        raw.extend_from_slice(&20_000_000u64.to_le_bytes());
+        raw.extend_from_slice(&u64::MAX.to_le_bytes());
+        raw.extend_from_slice(&1u64.to_le_bytes());
+        let Ok(limits) = FuelLimits::decode(&raw) else {
+            panic!("cannot decode fuel limits");
+        };
+        assert_eq!(limits.init, DEFAULT_FUEL);
+        assert_eq!(limits.boot, 20_000_000);
+        assert_eq!(limits.update, MAX_FUEL);
+        assert_eq!(limits.render, MIN_FUEL);
+        assert_eq!(limits.before_exit, DEFAULT_FUEL);
+        assert_eq!(limits.handle_data, DEFAULT_FUEL);
+        assert_eq!(limits.handle_menu, DEFAULT_FUEL);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(FuelLimits::decode(&[1, 2, 3]).is_err());
+        assert!(FuelLimits::decode(&[0; 8 * 8]).is_ok());
+        assert!(FuelLimits::decode(&[0; 9 * 8]).is_err());
+        assert!(FuelLimits::decode(&[]).is_ok());
+    }
+}
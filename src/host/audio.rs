@@ -1,9 +1,9 @@
 use super::fs::get_file_name;
+use crate::audio_log::{AudioCall, Lfo, NodeKind};
 use crate::error::HostError;
 use crate::state::State;
 use alloc::boxed::Box;
-use firefly_audio::*;
-use firefly_hal::Dir;
+use alloc::string::String;
 
 type C<'a, 'b> = wasmi::Caller<'a, Box<State<'b>>>;
 
@@ -11,56 +11,49 @@ type C<'a, 'b> = wasmi::Caller<'a, Box<State<'b>>>;
 pub(crate) fn add_sine(mut caller: C, parent_id: u32, freq: f32, phase: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_sine";
-    let proc = Sine::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Sine { freq, phase })
 }
 
 /// Add square wave generator as a child for the given node.
 pub(crate) fn add_square(mut caller: C, parent_id: u32, freq: f32, phase: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_square";
-    let proc = Square::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Square { freq, phase })
 }
 
 /// Add sawtooth wave generator as a child for the given node.
 pub(crate) fn add_sawtooth(mut caller: C, parent_id: u32, freq: f32, phase: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_sawtooth";
-    let proc = Sawtooth::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Sawtooth { freq, phase })
 }
 
 /// Add triangle wave generator as a child for the given node.
 pub(crate) fn add_triangle(mut caller: C, parent_id: u32, freq: f32, phase: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_triangle";
-    let proc = Triangle::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Triangle { freq, phase })
 }
 
 /// Add white noise generator as a child for the given node.
 pub(crate) fn add_noise(mut caller: C, parent_id: u32, seed: i32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_noise";
-    let proc = Noise::new(seed);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Noise { seed })
 }
 
 /// Add empty source as a child for the given node.
 pub(crate) fn add_empty(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_empty";
-    let proc = Empty::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Empty)
 }
 
 /// Add zero source as a child for the given node.
 pub(crate) fn add_zero(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_zero";
-    let proc = Zero::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Zero)
 }
 
 /// Add PCM file source as a child for the given node.
@@ -75,146 +68,126 @@ pub(crate) fn add_file(mut caller: C, parent_id: u32, ptr: u32, len: u32) -> u32
     let Some(name) = get_file_name(state, data, ptr, len) else {
         return 0;
     };
-    let reader = match state.rom_dir.open_file(name) {
-        Ok(reader) => reader,
-        Err(err) => {
-            state.log_error(err);
-            return 0;
-        }
-    };
-    let proc = match Pcm::from_file(reader) {
-        Ok(proc) => proc,
-        Err(err) => {
-            state.log_error(err);
-            return 0;
-        }
-    };
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::File(String::from(name)))
 }
 
 /// Add Mix filter as a child for the given node.
 pub(crate) fn add_mix(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_mix";
-    let proc = Mix::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Mix)
 }
 
 /// Add AllForOne filter as a child for the given node.
 pub(crate) fn add_all_for_one(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_all_for_one";
-    let proc = AllForOne::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::AllForOne)
 }
 
 /// Add Gain filter as a child for the given node.
 pub(crate) fn add_gain(mut caller: C, parent_id: u32, lvl: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_gain";
-    let proc = Gain::new(lvl);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Gain { lvl })
 }
 
 /// Add Loop filter as a child for the given node.
 pub(crate) fn add_loop(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_loop";
-    let proc = Loop::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Loop)
 }
 
 /// Add Concat filter as a child for the given node.
 pub(crate) fn add_concat(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_concat";
-    let proc = Concat::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Concat)
 }
 
 /// Add Pan filter as a child for the given node.
 pub(crate) fn add_pan(mut caller: C, parent_id: u32, lvl: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_pan";
-    let proc = Pan::new(lvl);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Pan { lvl })
 }
 
 /// Add Mute filter as a child for the given node.
 pub(crate) fn add_mute(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_mute";
-    let proc = Mute::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Mute)
 }
 
 /// Add Pause filter as a child for the given node.
 pub(crate) fn add_pause(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_pause";
-    let proc = Pause::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Pause)
 }
 
 /// Add TrackPosition filter as a child for the given node.
 pub(crate) fn add_track_position(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_track_position";
-    let proc = TrackPosition::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::TrackPosition)
 }
 
 /// Add LowHighPass filter as a child for the given node.
 pub(crate) fn add_low_pass(mut caller: C, parent_id: u32, freq: f32, q: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_low_pass";
-    let proc = LowHighPass::new(true, freq, q);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::LowPass { freq, q })
 }
 
 /// Add LowHighPass filter as a child for the given node.
 pub(crate) fn add_high_pass(mut caller: C, parent_id: u32, freq: f32, q: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_high_pass";
-    let proc = LowHighPass::new(false, freq, q);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::HighPass { freq, q })
 }
 
 /// Add TakeLeft filter as a child for the given node.
 pub(crate) fn add_take_left(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_take_left";
-    let proc = TakeLeft::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::TakeLeft)
 }
 
 /// Add TakeRight filter as a child for the given node.
 pub(crate) fn add_take_right(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_take_right";
-    let proc = TakeRight::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::TakeRight)
 }
 
 /// Add Swap filter as a child for the given node.
 pub(crate) fn add_swap(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_swap";
-    let proc = Swap::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Swap)
 }
 
 /// Add Clip filter as a child for the given node.
 pub(crate) fn add_clip(mut caller: C, parent_id: u32, low: f32, high: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_clip";
-    let proc = Clip::new(low, high);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Clip { low, high })
 }
 
-fn add_node(state: &mut State, parent_id: u32, proc: Box<dyn firefly_audio::Processor>) -> u32 {
+fn add_node(state: &mut State, parent_id: u32, node: NodeKind) -> u32 {
+    let Some(proc) = node.build(state) else {
+        return 0;
+    };
     match state.audio.add_node(parent_id, proc) {
-        Ok(id) => id,
+        Ok(id) => {
+            state.audio_log.push(AudioCall::Add {
+                parent: parent_id,
+                node,
+            });
+            id
+        }
         Err(err) => {
             state.log_error(HostError::AudioNode(err));
             0
@@ -237,6 +210,11 @@ pub(crate) fn set_param(mut caller: C, node_id: u32, param: u32, val: f32) {
         return;
     }
     node.set(param as u8, val);
+    state.audio_log.push(AudioCall::Set {
+        node: node_id,
+        param: param as u8,
+        val,
+    });
 }
 
 /// Modulate a parameter of the given node using linear modulation.
@@ -251,15 +229,15 @@ pub(crate) fn mod_linear(
 ) {
     let state = caller.data_mut();
     state.called = "audio.mod_linear";
-    let lfo = modulators::Linear::new(start_at, end_at);
-    modulate(state, node_id, param, Box::new(lfo), low, high);
+    let lfo = Lfo::Linear { start_at, end_at };
+    modulate(state, node_id, param, lfo, low, high);
 }
 
 pub(crate) fn mod_hold(mut caller: C, node_id: u32, param: u32, low: f32, high: f32, time: u32) {
     let state = caller.data_mut();
     state.called = "audio.mod_hold";
-    let lfo = modulators::Hold::new(time);
-    modulate(state, node_id, param, Box::new(lfo), low, high);
+    let lfo = Lfo::Hold { time };
+    modulate(state, node_id, param, lfo, low, high);
 }
 
 pub(crate) fn mod_adsr(
@@ -276,16 +254,22 @@ pub(crate) fn mod_adsr(
 ) {
     let state = caller.data_mut();
     state.called = "audio.mod_adsr";
-    let lfo = modulators::Adsr::new(attack, decay, sustain, sustain_level, release);
-    modulate(state, node_id, param, Box::new(lfo), low, high);
+    let lfo = Lfo::Adsr {
+        attack,
+        decay,
+        sustain,
+        sustain_level,
+        release,
+    };
+    modulate(state, node_id, param, lfo, low, high);
 }
 
 // TODO(@orsinium): Put `low` and `high` before modulator params for consistency.
 pub(crate) fn mod_sine(mut caller: C, node_id: u32, param: u32, freq: f32, low: f32, high: f32) {
     let state = caller.data_mut();
     state.called = "audio.mod_sine";
-    let lfo = modulators::Sine::new(freq);
-    modulate(state, node_id, param, Box::new(lfo), low, high);
+    let lfo = Lfo::Sine { freq };
+    modulate(state, node_id, param, lfo, low, high);
 }
 
 pub(crate) fn mod_square(
@@ -298,8 +282,8 @@ pub(crate) fn mod_square(
 ) {
     let state = caller.data_mut();
     state.called = "audio.mod_square";
-    let lfo = modulators::Pulse::new_square(period);
-    modulate(state, node_id, param, Box::new(lfo), low, high);
+    let lfo = Lfo::Square { period };
+    modulate(state, node_id, param, lfo, low, high);
 }
 
 pub(crate) fn mod_sawtooth(
@@ -312,18 +296,11 @@ pub(crate) fn mod_sawtooth(
 ) {
     let state = caller.data_mut();
     state.called = "audio.mod_sawtooth";
-    let lfo = modulators::Triangle::new_sawtooth(period);
-    modulate(state, node_id, param, Box::new(lfo), low, high);
+    let lfo = Lfo::Sawtooth { period };
+    modulate(state, node_id, param, lfo, low, high);
 }
 
-fn modulate(
-    state: &mut State,
-    node_id: u32,
-    param: u32,
-    lfo: Box<dyn modulators::Modulator>,
-    low: f32,
-    high: f32,
-) {
+fn modulate(state: &mut State, node_id: u32, param: u32, lfo: Lfo, low: f32, high: f32) {
     let node = match state.audio.get_node(node_id) {
         Ok(node) => node,
         Err(err) => {
@@ -335,7 +312,14 @@ fn modulate(
         state.log_error("param index is too high");
         return;
     }
-    node.modulate(param as u8, lfo, low, high);
+    node.modulate(param as u8, lfo.build(), low, high);
+    state.audio_log.push(AudioCall::Modulate {
+        node: node_id,
+        param: param as u8,
+        lfo,
+        low,
+        high,
+    });
 }
 
 /// Reset the given node.
@@ -362,8 +346,8 @@ pub(crate) fn reset_all(mut caller: C, node_id: u32) {
 pub(crate) fn clear(mut caller: C, node_id: u32) {
     let state = caller.data_mut();
     state.called = "audio.clear";
-    let res = state.audio.clear(node_id);
-    if let Err(err) = res {
-        state.log_error(HostError::AudioNode(err));
+    match state.audio.clear(node_id) {
+        Ok(()) => state.audio_log.push(AudioCall::Clear { node: node_id }),
+        Err(err) => state.log_error(HostError::AudioNode(err)),
     }
 }
@@ -1,17 +1,19 @@
-use crate::canvas::Canvas;
+use crate::canvas::{Canvas, MemoryView, split_memory};
 use crate::color::Rgb16;
 use crate::error::HostError;
+use crate::font::{Align, Font};
 use crate::frame_buffer::{HEIGHT, WIDTH};
-use crate::image::ParsedImage;
+use crate::image::{PackedTarget, ParsedImage, Remap, Transform, copy_packed, draw_transformed};
+use crate::pattern::{Pattern, PatternTarget, Patterned};
+use crate::polygon::Polygon;
 use crate::state::State;
 use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::convert::Infallible;
-use embedded_graphics::image::ImageRaw;
-use embedded_graphics::mono_font::{DecorationDimensions, MonoFont, MonoTextStyle, mapping};
 use embedded_graphics::pixelcolor::Gray4;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::*;
-use embedded_graphics::text::Text;
 
 type C<'a, 'b> = wasmi::Caller<'a, Box<State<'b>>>;
 
@@ -44,7 +46,14 @@ pub(crate) fn set_color(mut caller: C, index: u32, r: u32, g: u32, b: u32) {
         state.log_error("cannot set color for transparency");
         return;
     }
-    state.frame.palette[index as usize - 1] = Rgb16::from_rgb(r as u16, g as u16, b as u16);
+    let color = Rgb16::from_rgb(r as u16, g as u16, b as u16);
+    let old = &mut state.frame.palette[index as usize - 1];
+    if *old != color {
+        *old = color;
+        // Pixels on every row might have this color,
+        // so the whole frame must be redrawn with the new palette.
+        state.frame.mark_all_dirty();
+    }
 }
 
 /// Draw a single point.
@@ -57,14 +66,13 @@ pub(crate) fn draw_point(mut caller: C, x: i32, y: i32, color: i32) {
         state.log_error(HostError::NoneColor);
         return;
     }
-    let point = Point::new(x, y);
+    let point = Point::new(x, y) + state.offset;
     if let Some(canvas) = &state.canvas {
         let color = Gray4::new(color as u8 - 1);
         let pixel = Pixel(point, color);
         let mut target = canvas.clone().as_target(&mut caller);
         never_fails(pixel.draw(&mut target));
     } else {
-        state.frame.dirty = true;
         state.frame.set_pixel(point, color as u8 - 1);
     };
 }
@@ -86,20 +94,20 @@ pub(crate) fn draw_line(
         state.log_error(HostError::NoneColor);
         return;
     };
+    let start = Point::new(p1_x, p1_y) + state.offset;
+    let end = Point::new(p2_x, p2_y) + state.offset;
     if state.canvas.is_none() {
         let frame = &mut state.frame;
-        if p1_y == p2_y {
-            frame.draw_hline(p1_x, p2_x, p1_y, stroke_width, color);
+        if start.y == end.y {
+            frame.draw_hline(start.x, end.x, start.y, stroke_width, color);
             return;
         }
-        if p1_x == p2_x {
-            frame.draw_vline(p1_x, p1_y, p2_y, stroke_width, color);
+        if start.x == end.x {
+            frame.draw_vline(start.x, start.y, end.y, stroke_width, color);
             return;
         }
     }
 
-    let start = Point::new(p1_x, p1_y);
-    let end = Point::new(p2_x, p2_y);
     let line = Line::new(start, end);
     let style = PrimitiveStyle::with_stroke(color, stroke_width);
     let err = if let Some(canvas) = &state.canvas {
@@ -124,20 +132,14 @@ pub(crate) fn draw_rect(
 ) {
     let state = caller.data_mut();
     state.called = "graphics.draw_rect";
-    if x > WIDTH as i32 || y > HEIGHT as i32 {
+    let point = Point::new(x, y) + state.offset;
+    if point.x > WIDTH as i32 || point.y > HEIGHT as i32 {
         return;
     }
-    let point = Point::new(x, y);
     let size = Size::new(width, height);
     let rect = Rectangle::new(point, size);
     let style = get_shape_style(fill_color, stroke_color, stroke_width);
-    let err = if let Some(canvas) = &state.canvas {
-        let mut target = canvas.clone().as_target(&mut caller);
-        rect.draw_styled(&style, &mut target)
-    } else {
-        rect.draw_styled(&style, &mut state.frame)
-    };
-    never_fails(err);
+    draw_shape(&mut caller, &rect, style);
 }
 
 /// Draw a rectangle with rounded corners.
@@ -155,19 +157,13 @@ pub(crate) fn draw_rounded_rect(
 ) {
     let state = caller.data_mut();
     state.called = "graphics.draw_rounded_rect";
-    let point = Point::new(x, y);
+    let point = Point::new(x, y) + state.offset;
     let size = Size::new(width, height);
     let rect = Rectangle::new(point, size);
     let corner = Size::new(corner_width, corner_height);
     let rounded = RoundedRectangle::with_equal_corners(rect, corner);
     let style = get_shape_style(fill_color, stroke_color, stroke_width);
-    let err = if let Some(canvas) = &state.canvas {
-        let mut target = canvas.clone().as_target(&mut caller);
-        rounded.draw_styled(&style, &mut target)
-    } else {
-        rounded.draw_styled(&style, &mut state.frame)
-    };
-    never_fails(err);
+    draw_shape(&mut caller, &rounded, style);
 }
 
 /// Draw a circle.
@@ -182,16 +178,10 @@ pub(crate) fn draw_circle(
 ) {
     let state = caller.data_mut();
     state.called = "graphics.draw_circle";
-    let top_left = Point::new(x, y);
-    let style = get_shape_style(fill_color, stroke_color, stroke_width);
+    let top_left = Point::new(x, y) + state.offset;
     let circle = Circle::new(top_left, diameter);
-    let err = if let Some(canvas) = &state.canvas {
-        let mut target = canvas.clone().as_target(&mut caller);
-        circle.draw_styled(&style, &mut target)
-    } else {
-        circle.draw_styled(&style, &mut state.frame)
-    };
-    never_fails(err);
+    let style = get_shape_style(fill_color, stroke_color, stroke_width);
+    draw_shape(&mut caller, &circle, style);
 }
 
 /// Draw an ellipse.
@@ -207,17 +197,11 @@ pub(crate) fn draw_ellipse(
 ) {
     let state = caller.data_mut();
     state.called = "graphics.draw_ellipse";
-    let top_left = Point::new(x, y);
+    let top_left = Point::new(x, y) + state.offset;
     let size = Size::new(width, height);
-    let style = get_shape_style(fill_color, stroke_color, stroke_width);
     let ellipse = Ellipse::new(top_left, size);
-    let err = if let Some(canvas) = &state.canvas {
-        let mut target = canvas.clone().as_target(&mut caller);
-        ellipse.draw_styled(&style, &mut target)
-    } else {
-        ellipse.draw_styled(&style, &mut state.frame)
-    };
-    never_fails(err);
+    let style = get_shape_style(fill_color, stroke_color, stroke_width);
+    draw_shape(&mut caller, &ellipse, style);
 }
 
 /// Draw a line between two points.
@@ -235,18 +219,12 @@ pub(crate) fn draw_triangle(
 ) {
     let state = caller.data_mut();
     state.called = "graphics.draw_triangle";
-    let vertex1 = Point::new(p1_x, p1_y);
-    let vertex2 = Point::new(p2_x, p2_y);
-    let vertex3 = Point::new(p3_x, p3_y);
-    let style = get_shape_style(fill_color, stroke_color, stroke_width);
+    let vertex1 = Point::new(p1_x, p1_y) + state.offset;
+    let vertex2 = Point::new(p2_x, p2_y) + state.offset;
+    let vertex3 = Point::new(p3_x, p3_y) + state.offset;
     let triangle = Triangle::new(vertex1, vertex2, vertex3);
-    let err = if let Some(canvas) = &state.canvas {
-        let mut target = canvas.clone().as_target(&mut caller);
-        triangle.draw_styled(&style, &mut target)
-    } else {
-        triangle.draw_styled(&style, &mut state.frame)
-    };
-    never_fails(err);
+    let style = get_shape_style(fill_color, stroke_color, stroke_width);
+    draw_shape(&mut caller, &triangle, style);
 }
 
 /// Draw an arc.
@@ -263,7 +241,7 @@ pub(crate) fn draw_arc(
 ) {
     let state = caller.data_mut();
     state.called = "graphics.draw_arc";
-    let point = Point::new(x, y);
+    let point = Point::new(x, y) + state.offset;
     let angle_start = Angle::from_radians(angle_start.into());
     let angle_sweep = Angle::from_radians(angle_sweep.into());
     let style = get_shape_style(fill_color, stroke_color, stroke_width);
@@ -291,18 +269,84 @@ pub(crate) fn draw_sector(
 ) {
     let state = caller.data_mut();
     state.called = "graphics.draw_sector";
-    let point = Point::new(x, y);
+    let point = Point::new(x, y) + state.offset;
     let angle_start = Angle::from_radians(angle_start.into());
     let angle_sweep = Angle::from_radians(angle_sweep.into());
-    let style = get_shape_style(fill_color, stroke_color, stroke_width);
     let sector = Sector::new(point, diameter, angle_start, angle_sweep);
-    let err = if let Some(canvas) = &state.canvas {
-        let mut target = canvas.clone().as_target(&mut caller);
-        sector.draw_styled(&style, &mut target)
-    } else {
-        sector.draw_styled(&style, &mut state.frame)
+    let style = get_shape_style(fill_color, stroke_color, stroke_width);
+    draw_shape(&mut caller, &sector, style);
+}
+
+/// Draw connected lines between the given points.
+///
+/// The points are pairs of i32 little-endian coordinates.
+pub(crate) fn draw_polyline(mut caller: C, ptr: u32, len: u32, color: i32, stroke_width: u32) {
+    let state = caller.data_mut();
+    state.called = "graphics.draw_polyline";
+    let Some(color) = parse_color(color) else {
+        state.log_error(HostError::NoneColor);
+        return;
     };
-    never_fails(err);
+    let Some(points) = load_points(&mut caller, ptr, len) else {
+        return;
+    };
+    let polyline = Polyline::new(&points);
+    let style = PrimitiveStyle::with_stroke(color, stroke_width);
+    draw_shape(&mut caller, &polyline, style);
+}
+
+/// Draw a closed shape with the given vertices.
+///
+/// The points are pairs of i32 little-endian coordinates.
+pub(crate) fn draw_polygon(
+    mut caller: C,
+    ptr: u32,
+    len: u32,
+    fill_color: u32,
+    stroke_color: u32,
+    stroke_width: u32,
+) {
+    let state = caller.data_mut();
+    state.called = "graphics.draw_polygon";
+    let Some(points) = load_points(&mut caller, ptr, len) else {
+        return;
+    };
+    let polygon = Polygon::new(points);
+    let style = get_shape_style(fill_color, stroke_color, stroke_width);
+    draw_shape(&mut caller, &polygon, style);
+}
+
+/// Read the list of points from the guest memory and apply the draw offset.
+fn load_points(caller: &mut C, ptr: u32, len: u32) -> Option<Vec<Point>> {
+    let state = caller.data_mut();
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return None;
+    };
+    let (data, state) = memory.data_and_store_mut(caller);
+    let ptr = ptr as usize;
+    let len = len as usize;
+    let Some(end) = ptr.checked_add(len) else {
+        state.log_error(HostError::OomPointer);
+        return None;
+    };
+    let Some(bytes) = data.get(ptr..end) else {
+        state.log_error(HostError::OomPointer);
+        return None;
+    };
+    if !len.is_multiple_of(8) {
+        state.log_error("points buffer size must be a multiple of 8");
+        return None;
+    }
+    let points = bytes
+        .chunks_exact(8)
+        .map(|raw| {
+            let x = i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+            let y = i32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+            Point::new(x, y) + state.offset
+        })
+        .collect();
+    Some(points)
 }
 
 pub(crate) fn draw_qr(
@@ -345,7 +389,7 @@ pub(crate) fn draw_qr(
     // render QR code
     let width = ascii_img.find('\n').unwrap_or_default() as u32;
     let area = Rectangle {
-        top_left: Point::new(x, y),
+        top_left: Point::new(x, y) + state.offset,
         size: Size {
             width,
             height: width,
@@ -400,6 +444,131 @@ pub(crate) fn draw_text(
     };
     let (data, state) = memory.data_and_store_mut(&mut caller);
 
+    // The text and the font are read from the guest memory
+    // while the canvas (if any) in the same memory is modified.
+    let canvas = state.canvas.clone();
+    let (target, view) = split_memory(canvas.as_ref(), state.clip, data);
+    let Some((text, font)) = load_text(state, &view, text_ptr, text_len, font_ptr, font_len) else {
+        return;
+    };
+    let Some(mut color) = parse_color(color) else {
+        state.log_error(HostError::NoneColor);
+        return;
+    };
+    if let Some(remap) = &state.remap {
+        color = Gray4::new(remap[usize::from(color.luma())]);
+    }
+    let point = Point::new(x, y) + state.offset;
+    if let Some(mut target) = target {
+        font.draw(text, point, color, &mut target);
+    } else {
+        font.draw(text, point, color, &mut state.frame);
+    }
+}
+
+/// Draw the text wrapped to fit into the given box.
+///
+/// Alignment is 0 for left, 1 for center, and 2 for right.
+/// Returns the number of characters (Unicode code points) that fit into the box,
+/// including spaces and line breaks.
+pub(crate) fn draw_text_box(
+    mut caller: C,
+    text_ptr: u32,
+    text_len: u32,
+    font_ptr: u32,
+    font_len: u32,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    align: u32,
+    line_spacing: u32,
+    char_spacing: u32,
+    color: i32,
+) -> u32 {
+    let state = caller.data_mut();
+    state.called = "graphics.draw_text_box";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let canvas = state.canvas.clone();
+    let (target, view) = split_memory(canvas.as_ref(), state.clip, data);
+    let Some((text, font)) = load_text(state, &view, text_ptr, text_len, font_ptr, font_len) else {
+        return 0;
+    };
+    let Some(mut color) = parse_color(color) else {
+        state.log_error(HostError::NoneColor);
+        return 0;
+    };
+    if let Some(remap) = &state.remap {
+        color = Gray4::new(remap[usize::from(color.luma())]);
+    }
+    let align = match align {
+        0 => Align::Left,
+        1 => Align::Center,
+        2 => Align::Right,
+        _ => {
+            state.log_error("unknown text alignment");
+            return 0;
+        }
+    };
+    let point = Point::new(x, y) + state.offset;
+    let area = Rectangle::new(point, Size::new(width, height));
+    let n_chars = if let Some(mut target) = target {
+        font.draw_box(
+            text,
+            area,
+            align,
+            line_spacing,
+            char_spacing,
+            color,
+            &mut target,
+        )
+    } else {
+        let target = &mut state.frame;
+        font.draw_box(text, area, align, line_spacing, char_spacing, color, target)
+    };
+    n_chars as u32
+}
+
+/// Get the size of the text if it was drawn with the given font.
+///
+/// The width is in the lower 16 bits of the result, the height is in the upper 16 bits.
+pub(crate) fn measure_text(
+    mut caller: C,
+    text_ptr: u32,
+    text_len: u32,
+    font_ptr: u32,
+    font_len: u32,
+) -> u32 {
+    let state = caller.data_mut();
+    state.called = "graphics.measure_text";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let view = MemoryView::new(data);
+    let Some((text, font)) = load_text(state, &view, text_ptr, text_len, font_ptr, font_len) else {
+        return 0;
+    };
+    let size = font.measure(text);
+    let width = size.width.min(0xffff);
+    let height = size.height.min(0xffff);
+    (height << 16) | width
+}
+
+/// Read the text and parse the font from the guest memory.
+fn load_text<'a>(
+    state: &mut State,
+    view: &MemoryView<'a>,
+    text_ptr: u32,
+    text_len: u32,
+    font_ptr: u32,
+    font_len: u32,
+) -> Option<(&'a str, Font<'a>)> {
     let text_ptr = text_ptr as usize;
     let text_len = text_len as usize;
     let font_ptr = font_ptr as usize;
@@ -409,39 +578,114 @@ pub(crate) fn draw_text(
     // There also used to be a check that the slices don't intersect
     // but on practice if font is statically allocated, LLVM can optimize
     // the data section and find the text bytes within the font.
-    let Some(text_bytes) = &data.get(text_ptr..(text_ptr + text_len)) else {
-        state.log_error(HostError::OomPointer);
-        return;
+    let text_bytes = match view.get(text_ptr, text_len) {
+        Ok(text_bytes) => text_bytes,
+        Err(err) => {
+            state.log_error(err);
+            return None;
+        }
     };
     if font_ptr == 0 {
         state.log_error("font is a nil pointer: make sure you've loaded it");
-        return;
+        return None;
     }
-    let Some(font_bytes) = &data.get(font_ptr..(font_ptr + font_len)) else {
-        state.log_error(HostError::OomPointer);
-        return;
+    let font_bytes = match view.get(font_ptr, font_len) {
+        Ok(font_bytes) => font_bytes,
+        Err(err) => {
+            state.log_error(err);
+            return None;
+        }
     };
-    let font = match parse_font(font_bytes) {
+    let font = match Font::parse(font_bytes) {
         Ok(font) => font,
         Err(err) => {
             state.log_error(err);
-            return;
+            return None;
         }
     };
-    let Some(color) = parse_color(color) else {
-        state.log_error(HostError::NoneColor);
-        return;
-    };
-    let style = MonoTextStyle::new(&font, color);
-    let point = Point::new(x, y);
     let Ok(text) = core::str::from_utf8(text_bytes) else {
         let msg = "the given text is not valid UTF-8";
         state.log_error(msg);
+        return None;
+    };
+    Some((text, font))
+}
+
+/// Get the color of the pixel at the given point of the current draw target.
+///
+/// Returns 0 if the point is out of bounds.
+pub(crate) fn get_pixel(mut caller: C, x: i32, y: i32) -> i32 {
+    let state = caller.data_mut();
+    state.called = "graphics.get_pixel";
+    let point = Point::new(x, y) + state.offset;
+    let luma = match (state.canvas.clone(), state.memory) {
+        (Some(canvas), Some(memory)) => canvas.get_pixel(memory.data(&caller), point),
+        _ => state.frame.get_pixel(point.x as usize, point.y as usize),
+    };
+    luma.map_or(0, |luma| i32::from(luma) + 1)
+}
+
+/// Copy a region of the current draw target into the guest memory.
+///
+/// The pixels are tightly packed 4 bits per pixel with the first pixel
+/// in the high nibble, just like in images. Out-of-bounds pixels are 0.
+pub(crate) fn read_region(
+    mut caller: C,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    ptr: u32,
+    len: u32,
+) {
+    let state = caller.data_mut();
+    state.called = "graphics.read_region";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let width = width as usize;
+    // On the device, usize is 32 bits, so the region size may overflow.
+    let Some(n_pixels) = width.checked_mul(height as usize) else {
+        state.log_error(HostError::SizeOverflow);
+        return;
+    };
+    let size = n_pixels.div_ceil(2);
+    if (len as usize) < size {
+        state.log_error("the buffer is too small for the region");
+        return;
+    }
+    let ptr = ptr as usize;
+    let Some(end) = ptr.checked_add(size) else {
+        state.log_error(HostError::SizeOverflow);
         return;
     };
-    let text = Text::new(text, point, style);
-    // TODO: support canvas
-    never_fails(text.draw(&mut state.frame));
+    if data.get(ptr..end).is_none() {
+        state.log_error(HostError::OomPointer);
+        return;
+    }
+
+    // The canvas is a part of the same memory, so the pixels are copied
+    // into a temporary buffer first.
+    let origin = Point::new(x, y) + state.offset;
+    let mut buf = vec![0u8; size];
+    for i in 0..n_pixels {
+        // Pixels that would be beyond i32 are out of bounds of any target.
+        let px = origin.x.checked_add((i % width) as i32);
+        let py = origin.y.checked_add((i / width) as i32);
+        let (Some(px), Some(py)) = (px, py) else {
+            continue;
+        };
+        let point = Point::new(px, py);
+        let luma = match &state.canvas {
+            Some(canvas) => canvas.get_pixel(data, point),
+            None => state.frame.get_pixel(point.x as usize, point.y as usize),
+        };
+        let luma = luma.unwrap_or(0);
+        buf[i / 2] |= if i.is_multiple_of(2) { luma << 4 } else { luma };
+    }
+    data[ptr..end].copy_from_slice(&buf);
 }
 
 /// Set an image localted in the guest memory as the draw target for all graphic operations.
@@ -488,6 +732,215 @@ pub(crate) fn unset_canvas(mut caller: C) {
     state.canvas = None;
 }
 
+/// Replace colors when drawing images, canvases, and text.
+///
+/// The table in the guest memory is 16 bytes, one for each color:
+/// the byte at index 3 is the (0-based) color that replaces the color 3.
+pub(crate) fn set_remap(mut caller: C, ptr: u32) {
+    let state = caller.data_mut();
+    state.called = "graphics.set_remap";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let ptr = ptr as usize;
+    let Some(end) = ptr.checked_add(16) else {
+        state.log_error(HostError::OomPointer);
+        return;
+    };
+    let Some(raw) = data.get(ptr..end) else {
+        state.log_error(HostError::OomPointer);
+        return;
+    };
+    let mut remap: Remap = [0; 16];
+    remap.copy_from_slice(raw);
+    if remap.iter().any(|c| *c > 15) {
+        state.log_error("remap table contains colors out of the palette");
+        return;
+    }
+    state.remap = Some(remap);
+}
+
+/// Draw all colors as is.
+pub(crate) fn unset_remap(mut caller: C) {
+    let state = caller.data_mut();
+    state.called = "graphics.unset_remap";
+    state.remap = None;
+}
+
+/// Fill shapes with a two-color pattern instead of a solid color.
+///
+/// The pattern kinds:
+///
+/// * 0: checkerboard.
+/// * 1: Bayer 2x2 ordered dithering, `arg` is the level from 0 to 4.
+/// * 2: Bayer 4x4 ordered dithering, `arg` is the level from 0 to 16.
+/// * 3: custom 4x4 pattern, `arg` is a 16-bit mask, bit `y * 4 + x` is the pixel (x, y).
+///
+/// The pattern pixels are drawn with the given color
+/// and the rest of pixels with the fill color of the shape.
+pub(crate) fn set_fill_pattern(mut caller: C, kind: u32, arg: u32, color: i32) {
+    let state = caller.data_mut();
+    state.called = "graphics.set_fill_pattern";
+    let Some(color) = parse_color(color) else {
+        state.log_error(HostError::NoneColor);
+        return;
+    };
+    let pattern = match kind {
+        0 => Pattern::checkerboard(color),
+        1 if arg <= 4 => Pattern::bayer2(arg as u8, color),
+        2 if arg <= 16 => Pattern::bayer4(arg as u8, color),
+        1 | 2 => {
+            state.log_error("dithering level is out of range");
+            return;
+        }
+        3 if arg <= 0xffff => Pattern::new(arg as u16, color),
+        3 => {
+            state.log_error(HostError::ValueTooBig);
+            return;
+        }
+        _ => {
+            state.log_error("unknown fill pattern");
+            return;
+        }
+    };
+    state.fill_pattern = Some(pattern);
+}
+
+/// Fill shapes with a solid color.
+pub(crate) fn unset_fill_pattern(mut caller: C) {
+    let state = caller.data_mut();
+    state.called = "graphics.unset_fill_pattern";
+    state.fill_pattern = None;
+}
+
+/// Restrict all drawing to the given rectangle.
+///
+/// The clip area is in the coordinates of the current draw target
+/// (the screen or the canvas) and stays active when the canvas is changed.
+pub(crate) fn set_clip(mut caller: C, x: i32, y: i32, width: u32, height: u32) {
+    let state = caller.data_mut();
+    state.called = "graphics.set_clip";
+    let clip = Rectangle::new(Point::new(x, y), Size::new(width, height));
+    state.clip = Some(clip);
+    state.frame.set_clip(state.clip);
+}
+
+/// Allow drawing on the whole draw target.
+pub(crate) fn unset_clip(mut caller: C) {
+    let state = caller.data_mut();
+    state.called = "graphics.unset_clip";
+    state.clip = None;
+    state.frame.set_clip(None);
+}
+
+/// Shift all subsequent drawing by the given number of pixels.
+///
+/// Drawing at (x, y) puts the pixel at (x + offset_x, y + offset_y).
+/// For scrolling, the offset is the negated camera position.
+/// The clip area is not affected by the offset.
+pub(crate) fn set_offset(mut caller: C, x: i32, y: i32) {
+    let state = caller.data_mut();
+    state.called = "graphics.set_offset";
+    state.offset = Point::new(x, y);
+}
+
+/// Draw a region of a canvas with the given transformation.
+///
+/// If another canvas is set as the draw target, the region is drawn on it.
+/// See [`Transform::from_flags`] for the flags format.
+/// The transparency color is 1-based, 0 means that all pixels are drawn.
+/// If the region size is 0x0, the whole canvas is drawn.
+pub(crate) fn draw_canvas(
+    mut caller: C,
+    ptr: u32,
+    len: u32,
+    x: i32,
+    y: i32,
+    sub_x: u32,
+    sub_y: u32,
+    sub_width: u32,
+    sub_height: u32,
+    flags: u32,
+    scale: u32,
+    transp: i32,
+) {
+    const HEADER: usize = 4;
+
+    let state = caller.data_mut();
+    state.called = "graphics.draw_canvas";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let canvas = state.canvas.clone();
+    let (mut canvas, view) = split_memory(canvas.as_ref(), state.clip, data);
+    let canvas_bytes = match view.get(ptr as usize, len as usize) {
+        Ok(canvas_bytes) => canvas_bytes,
+        Err(err) => {
+            state.log_error(err);
+            return;
+        }
+    };
+    if canvas_bytes.len() < HEADER {
+        state.log_error("canvas is too small");
+        return;
+    }
+    if canvas_bytes[0] != 0x22 {
+        state.log_error("invalid magic number");
+        return;
+    }
+    let width = u32::from(u16::from_le_bytes([canvas_bytes[1], canvas_bytes[2]]));
+    let canvas_bytes = &canvas_bytes[HEADER..];
+    if width == 0 || !(canvas_bytes.len() as u32 * 2).is_multiple_of(width) {
+        state.log_error(HostError::InvalidWidth);
+        return;
+    }
+    let height = canvas_bytes.len() as u32 * 2 / width;
+
+    let (sub_width, sub_height) = if sub_width == 0 && sub_height == 0 {
+        (width, height)
+    } else {
+        (sub_width, sub_height)
+    };
+    let fits_x = sub_x.checked_add(sub_width).is_some_and(|r| r <= width);
+    let fits_y = sub_y.checked_add(sub_height).is_some_and(|b| b <= height);
+    if !fits_x || !fits_y {
+        state.log_error("the region is out of the canvas bounds");
+        return;
+    }
+
+    let target: &mut dyn PackedTarget = match &mut canvas {
+        Some(canvas) => canvas,
+        None => &mut state.frame,
+    };
+    let point = Point::new(x, y) + state.offset;
+    let size = Size::new(sub_width, sub_height);
+    let transform = Transform::from_flags(flags, scale);
+    let transp = parse_color(transp).map(|c| c.into_storage());
+    // Canvas pixels are stored just like in the frame buffer: the even pixel first.
+    // So, if nothing changes the pixels, they can be copied as is.
+    if transform.is_identity() && transp.is_none() && state.remap.is_none() && !target.is_clipped()
+    {
+        let sub = Rectangle::new(Point::new(sub_x as i32, sub_y as i32), size);
+        copy_packed(target, point, canvas_bytes, width as usize, sub);
+        return;
+    }
+    let get = |px: u32, py: u32| {
+        let i = ((sub_y + py) * width + sub_x + px) as usize;
+        let byte = canvas_bytes[i / 2];
+        if i.is_multiple_of(2) {
+            byte & 0xf
+        } else {
+            byte >> 4
+        }
+    };
+    let remap = state.remap.as_ref();
+    draw_transformed(target, point, size, transform, transp, remap, get);
+}
+
 const IMG_HEADER: usize = 4;
 
 /// Tile the given screen area with the provided sub-image.
@@ -516,22 +969,12 @@ pub(crate) fn draw_sub_tile(
         return;
     };
     let (data, state) = memory.data_and_store_mut(&mut caller);
-    let Some(image_bytes) = load_image(state, data, ptr, len) else {
+    let canvas = state.canvas.clone();
+    let (mut canvas, view) = split_memory(canvas.as_ref(), state.clip, data);
+    let Some(mut image) = load_image(state, &view, ptr, len) else {
         return;
     };
 
-    let width = u16::from_le_bytes([image_bytes[1], image_bytes[2]]) as u32;
-    if width == 0 {
-        state.log_error("image has zero width");
-        return;
-    }
-    let transp = u8::from_le_bytes([image_bytes[3]]);
-    let image_bytes = &image_bytes[IMG_HEADER..];
-    if !(image_bytes.len() * 2).is_multiple_of(width as usize) {
-        state.log_error(HostError::InvalidWidth);
-        return;
-    }
-
     if !(w as u32).is_multiple_of(sub_width) {
         state.log_error("area width must be a multiple of sib-image width");
         return;
@@ -541,27 +984,194 @@ pub(crate) fn draw_sub_tile(
         return;
     }
 
-    // TODO: support canvas
-    if state.canvas.is_some() {
-        state.log_error("images cannot be drawn on canvas yet");
-        return;
-    }
-
     let sub_point = Point::new(sub_x, sub_y);
     let sub_size = Size::new(sub_width, sub_height);
     let sub = Rectangle::new(sub_point, sub_size);
 
-    let image = ParsedImage {
-        bytes: image_bytes,
-        width,
-        transp,
-        sub: Some(sub),
+    let target: &mut dyn PackedTarget = match &mut canvas {
+        Some(canvas) => canvas,
+        None => &mut state.frame,
     };
+    image.sub = Some(sub);
 
+    let Point { x, y } = Point::new(x, y) + state.offset;
     for px in (x..x + w).step_by(sub_width as _) {
         for py in (y..y + h).step_by(sub_height as _) {
             let point = Point::new(px, py);
-            image.render(point, &mut state.frame);
+            image.render(point, target);
+        }
+    }
+}
+
+/// Tile index marking an empty cell of the tilemap.
+const EMPTY_TILE: u16 = 0x3fff;
+
+/// Render the visible part of a tilemap using tiles from the given tileset image.
+///
+/// The tilemap is an array of u16 little-endian entries, `map_width` tiles per row.
+/// The lower 14 bits of each entry is the index of the tile in the tileset
+/// (counting left-to-right, top-to-bottom), bit 14 flips the tile horizontally,
+/// and bit 15 flips it vertically. The index 0x3fff marks an empty cell.
+///
+/// The tilemap pixel at (`scroll_x`, `scroll_y`) is drawn at the top-left
+/// corner of the screen area. Nothing is drawn outside of the area.
+pub(crate) fn draw_tilemap(
+    mut caller: C,
+    ptr: u32,
+    len: u32,
+    tiles_ptr: u32,
+    tiles_len: u32,
+    tile_width: u32,
+    tile_height: u32,
+    map_width: u32,
+    // Screen area to fill.
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+    // Position of the tilemap pixel to draw at the area top-left corner.
+    scroll_x: i32,
+    scroll_y: i32,
+) {
+    let state = caller.data_mut();
+    state.called = "graphics.draw_tilemap";
+
+    // Retrieve the raw data from memory.
+    let state = caller.data_mut();
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let canvas = state.canvas.clone();
+    let (mut canvas, view) = split_memory(canvas.as_ref(), state.clip, data);
+    let Some(image) = load_image(state, &view, ptr, len) else {
+        return;
+    };
+    // The tiles are drawn one by one, so a compressed tileset
+    // is decoded once for all tiles instead of once for each tile.
+    let raw = image.decompress();
+    let mut image = match &raw {
+        Some(raw) => image.decoded(raw),
+        None => image,
+    };
+    let tiles = match view.get(tiles_ptr as usize, tiles_len as usize) {
+        Ok(tiles) => tiles,
+        Err(err) => {
+            state.log_error(err);
+            return;
+        }
+    };
+
+    if tile_width == 0 || tile_height == 0 {
+        state.log_error("tile size must not be zero");
+        return;
+    }
+    let n_entries = tiles.len() / 2;
+    if map_width == 0
+        || !tiles.len().is_multiple_of(2)
+        || !n_entries.is_multiple_of(map_width as usize)
+    {
+        state.log_error("tilemap size must be a multiple of the tilemap width");
+        return;
+    }
+    let columns = image.width / tile_width;
+    let n_tiles = columns * (image.height() / tile_height);
+    if n_tiles == 0 {
+        state.log_error("tile size must not be bigger than the tileset");
+        return;
+    }
+    // All the math below is done in i64 so that huge sizes, positions,
+    // and scroll values passed by the app can't overflow.
+    let map_width = i64::from(map_width);
+    let map_height = n_entries as i64 / map_width;
+    let tw = i64::from(tile_width);
+    let th = i64::from(tile_height);
+    let x = i64::from(x) + i64::from(state.offset.x);
+    let y = i64::from(y) + i64::from(state.offset.y);
+
+    let target: &mut dyn PackedTarget = match &mut canvas {
+        Some(canvas) => canvas,
+        None => &mut state.frame,
+    };
+    // Clip the area to the target bounds.
+    let max = i64::from(i32::MAX);
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x + i64::from(w)).min((target.width() as i64).min(max));
+    let bottom = (y + i64::from(h)).min((target.height() as i64).min(max));
+    if left >= right || top >= bottom {
+        return;
+    }
+    // Scroll the tilemap by the part of the area cut on the left and top.
+    let scroll_x = i64::from(scroll_x) + left - x;
+    let scroll_y = i64::from(scroll_y) + top - y;
+    let area = Rectangle::new(
+        Point::new(left as i32, top as i32),
+        Size::new((right - left) as u32, (bottom - top) as u32),
+    );
+
+    // The range of tiles intersecting with the area.
+    let col_start = scroll_x.div_euclid(tw).max(0);
+    let col_end = (scroll_x + right - left - 1)
+        .div_euclid(tw)
+        .min(map_width - 1);
+    let row_start = scroll_y.div_euclid(th).max(0);
+    let row_end = (scroll_y + bottom - top - 1)
+        .div_euclid(th)
+        .min(map_height - 1);
+
+    for row in row_start..=row_end {
+        for col in col_start..=col_end {
+            let i = (row * map_width + col) as usize * 2;
+            let entry = u16::from_le_bytes([tiles[i], tiles[i + 1]]);
+            let index = entry & EMPTY_TILE;
+            if index == EMPTY_TILE || u32::from(index) >= n_tiles {
+                continue;
+            }
+            let flip_x = entry & (1 << 14) != 0;
+            let flip_y = entry & (1 << 15) != 0;
+
+            // Clip the tile to the area. The tile is never bigger than
+            // the tileset and it intersects with the area,
+            // so its position fits into i32.
+            let point = Point::new(
+                (left + col * tw - scroll_x) as i32,
+                (top + row * th - scroll_y) as i32,
+            );
+            let tile = Rectangle::new(point, Size::new(tile_width, tile_height));
+            let visible = tile.intersection(&area);
+            if visible.is_zero_sized() {
+                continue;
+            }
+
+            // Find the visible part of the tile in the tileset.
+            let offset = visible.top_left - point;
+            let size = visible.size;
+            let mut sub_x = offset.x;
+            let mut sub_y = offset.y;
+            if flip_x {
+                sub_x = tile_width as i32 - offset.x - size.width as i32;
+            }
+            if flip_y {
+                sub_y = tile_height as i32 - offset.y - size.height as i32;
+            }
+            let index = u32::from(index);
+            sub_x += ((index % columns) * tile_width) as i32;
+            sub_y += ((index / columns) * tile_height) as i32;
+            image.sub = Some(Rectangle::new(Point::new(sub_x, sub_y), size));
+
+            if flip_x || flip_y {
+                let transform = Transform {
+                    flip_x,
+                    flip_y,
+                    rotate: 0,
+                    scale: 1,
+                };
+                image.render_transformed(visible.top_left, transform, target);
+            } else {
+                image.render(visible.top_left, target);
+            }
         }
     }
 }
@@ -594,36 +1204,22 @@ pub(crate) fn draw_nine_slice(
         return;
     };
     let (data, state) = memory.data_and_store_mut(&mut caller);
-    let Some(image_bytes) = load_image(state, data, ptr, len) else {
+    let canvas = state.canvas.clone();
+    let (mut canvas, view) = split_memory(canvas.as_ref(), state.clip, data);
+    let Some(image) = load_image(state, &view, ptr, len) else {
         return;
     };
-
-    let width = u16::from_le_bytes([image_bytes[1], image_bytes[2]]) as u32;
-    if width == 0 {
-        state.log_error("image has zero width");
-        return;
-    }
-    let transp = u8::from_le_bytes([image_bytes[3]]);
-    let image_bytes = &image_bytes[IMG_HEADER..];
-    if !(image_bytes.len() * 2).is_multiple_of(width as usize) {
-        state.log_error(HostError::InvalidWidth);
-        return;
-    }
-
-    // TODO: support canvas
-    if state.canvas.is_some() {
-        state.log_error("images cannot be drawn on canvas yet");
-        return;
-    }
-
-    let height = image_bytes.len() as u32 * 2 / width;
-    let mut image = ParsedImage {
-        bytes: image_bytes,
-        width,
-        transp,
-        sub: None,
+    // The image is drawn in many segments, so a compressed image
+    // is decoded once for all segments instead of once for each segment.
+    let raw = image.decompress();
+    let mut image = match &raw {
+        Some(raw) => image.decoded(raw),
+        None => image,
     };
 
+    let width = image.width;
+    let height = image.height();
+
     // Make sure that all segments of 9-slice fully fit into the area.
     // This limitation might be relaxed or removed in the future
     // when the implementation handles such cases correctly
@@ -645,6 +1241,8 @@ pub(crate) fn draw_nine_slice(
         return;
     }
 
+    let Point { x, y } = Point::new(x, y) + state.offset;
+
     // Segment corners.
     let x1 = 0i32;
     // TODO: handle mid_x being outside the image.
@@ -664,13 +1262,18 @@ pub(crate) fn draw_nine_slice(
     let hm = (y3 - y2) as u32;
     let hb = (y4 - y3) as u32;
 
+    let target: &mut dyn PackedTarget = match &mut canvas {
+        Some(canvas) => canvas,
+        None => &mut state.frame,
+    };
+
     // Top-left corner.
     {
         let size = Size::new(wl, ht);
         let point = Point::new(x1, y1);
         image.sub = Some(Rectangle::new(point, size));
         let point = Point::new(x, y);
-        image.render(point, &mut state.frame);
+        image.render(point, target);
     }
     // Top-right corner.
     {
@@ -678,7 +1281,7 @@ pub(crate) fn draw_nine_slice(
         let point = Point::new(x3, y1);
         image.sub = Some(Rectangle::new(point, size));
         let point = Point::new(x + w - wr as i32, y);
-        image.render(point, &mut state.frame);
+        image.render(point, target);
     }
     // Bottom-left corner.
     {
@@ -686,7 +1289,7 @@ pub(crate) fn draw_nine_slice(
         let point = Point::new(x1, y3);
         image.sub = Some(Rectangle::new(point, size));
         let point = Point::new(x, y + h - hb as i32);
-        image.render(point, &mut state.frame);
+        image.render(point, target);
     }
     // Bottom-right corner.
     {
@@ -694,7 +1297,7 @@ pub(crate) fn draw_nine_slice(
         let point = Point::new(x3, y3);
         image.sub = Some(Rectangle::new(point, size));
         let point = Point::new(x + w - wr as i32, y + h - hb as i32);
-        image.render(point, &mut state.frame);
+        image.render(point, target);
     }
 
     // Top edge.
@@ -704,7 +1307,7 @@ pub(crate) fn draw_nine_slice(
         let point = Point::new(x2, y1);
         image.sub = Some(Rectangle::new(point, size));
         for sx in range_x.clone() {
-            image.render(Point::new(sx, y), &mut state.frame);
+            image.render(Point::new(sx, y), target);
         }
     }
     // Bottom edge.
@@ -714,7 +1317,7 @@ pub(crate) fn draw_nine_slice(
         image.sub = Some(Rectangle::new(point, size));
         let sy = y + h - hb as i32;
         for sx in range_x.clone() {
-            image.render(Point::new(sx, sy), &mut state.frame);
+            image.render(Point::new(sx, sy), target);
         }
     }
     // Left edge.
@@ -724,7 +1327,7 @@ pub(crate) fn draw_nine_slice(
         let point = Point::new(x1, y2);
         image.sub = Some(Rectangle::new(point, size));
         for sy in range_y.clone() {
-            image.render(Point::new(x, sy), &mut state.frame);
+            image.render(Point::new(x, sy), target);
         }
     }
     // Right edge.
@@ -734,7 +1337,7 @@ pub(crate) fn draw_nine_slice(
         image.sub = Some(Rectangle::new(point, size));
         let sx = x + w - wr as i32;
         for sy in range_y.clone() {
-            image.render(Point::new(sx, sy), &mut state.frame);
+            image.render(Point::new(sx, sy), target);
         }
     }
 
@@ -745,7 +1348,7 @@ pub(crate) fn draw_nine_slice(
         image.sub = Some(Rectangle::new(point, size));
         for sy in range_y {
             for sx in range_x.clone() {
-                image.render(Point::new(sx, sy), &mut state.frame);
+                image.render(Point::new(sx, sy), target);
             }
         }
     }
@@ -767,16 +1370,69 @@ pub(crate) fn draw_sub_image(
     let sub_point = Point::new(sub_x, sub_y);
     let sub_size = Size::new(sub_width, sub_height);
     let sub = Rectangle::new(sub_point, sub_size);
-    draw_image_inner(caller, ptr, len, x, y, Some(sub))
+    let transform = Transform::from_flags(0, 1);
+    draw_image_inner(caller, ptr, len, x, y, Some(sub), transform)
 }
 
 pub(crate) fn draw_image(mut caller: C, ptr: u32, len: u32, x: i32, y: i32) {
     let state = caller.data_mut();
     state.called = "graphics.draw_image";
-    draw_image_inner(caller, ptr, len, x, y, None)
+    let transform = Transform::from_flags(0, 1);
+    draw_image_inner(caller, ptr, len, x, y, None, transform)
+}
+
+/// Draw the image flipped, rotated, and/or scaled.
+///
+/// See [`Transform::from_flags`] for the flags format.
+pub(crate) fn draw_image_transformed(
+    mut caller: C,
+    ptr: u32,
+    len: u32,
+    x: i32,
+    y: i32,
+    flags: u32,
+    scale: u32,
+) {
+    let state = caller.data_mut();
+    state.called = "graphics.draw_image_transformed";
+    let transform = Transform::from_flags(flags, scale);
+    draw_image_inner(caller, ptr, len, x, y, None, transform)
+}
+
+/// Draw a region of the image flipped, rotated, and/or scaled.
+///
+/// See [`Transform::from_flags`] for the flags format.
+pub(crate) fn draw_sub_image_transformed(
+    mut caller: C,
+    ptr: u32,
+    len: u32,
+    x: i32,
+    y: i32,
+    sub_x: i32,
+    sub_y: i32,
+    sub_width: u32,
+    sub_height: u32,
+    flags: u32,
+    scale: u32,
+) {
+    let state = caller.data_mut();
+    state.called = "graphics.draw_sub_image_transformed";
+    let sub_point = Point::new(sub_x, sub_y);
+    let sub_size = Size::new(sub_width, sub_height);
+    let sub = Rectangle::new(sub_point, sub_size);
+    let transform = Transform::from_flags(flags, scale);
+    draw_image_inner(caller, ptr, len, x, y, Some(sub), transform)
 }
 
-fn draw_image_inner(mut caller: C, ptr: u32, len: u32, x: i32, y: i32, sub: Option<Rectangle>) {
+fn draw_image_inner(
+    mut caller: C,
+    ptr: u32,
+    len: u32,
+    x: i32,
+    y: i32,
+    sub: Option<Rectangle>,
+    transform: Transform,
+) {
     // Retrieve the raw data from memory.
     let state = caller.data_mut();
     let Some(memory) = state.memory else {
@@ -784,45 +1440,42 @@ fn draw_image_inner(mut caller: C, ptr: u32, len: u32, x: i32, y: i32, sub: Opti
         return;
     };
     let (data, state) = memory.data_and_store_mut(&mut caller);
-    let Some(image_bytes) = load_image(state, data, ptr, len) else {
+    let canvas = state.canvas.clone();
+    let (mut canvas, view) = split_memory(canvas.as_ref(), state.clip, data);
+    let Some(mut image) = load_image(state, &view, ptr, len) else {
         return;
     };
 
-    let width = u16::from_le_bytes([image_bytes[1], image_bytes[2]]) as u32;
-    if width == 0 {
-        state.log_error("image has zero width");
-        return;
-    }
-    let transp = u8::from_le_bytes([image_bytes[3]]);
-    let image_bytes = &image_bytes[IMG_HEADER..];
-    if !(image_bytes.len() * 2).is_multiple_of(width as usize) {
-        state.log_error(HostError::InvalidWidth);
-        return;
-    }
-
-    // TODO: support canvas
-    if state.canvas.is_some() {
-        state.log_error("images cannot be drawn on canvas yet");
-        return;
-    }
-
-    let point = Point::new(x, y);
-    let image = ParsedImage {
-        bytes: image_bytes,
-        width,
-        transp,
-        sub,
+    let point = Point::new(x, y) + state.offset;
+    let target: &mut dyn PackedTarget = match &mut canvas {
+        Some(canvas) => canvas,
+        None => &mut state.frame,
     };
-    image.render(point, &mut state.frame);
+    image.sub = sub;
+    if transform.is_identity() {
+        image.render(point, target);
+    } else {
+        image.render_transformed(point, transform, target);
+    }
 }
 
-fn load_image<'a>(state: &mut State, data: &'a [u8], ptr: u32, len: u32) -> Option<&'a [u8]> {
+/// Read the image from the guest memory and parse its header.
+///
+/// The current remap table (if any) is attached to the image.
+fn load_image<'a>(
+    state: &mut State,
+    view: &MemoryView<'a>,
+    ptr: u32,
+    len: u32,
+) -> Option<ParsedImage<'a>> {
     let ptr = ptr as usize;
     let len = len as usize;
-    let maybe_buf = data.get(ptr..(ptr + len));
-    let Some(image_bytes) = &maybe_buf else {
-        state.log_error(HostError::OomPointer);
-        return None;
+    let image_bytes = match view.get(ptr, len) {
+        Ok(image_bytes) => image_bytes,
+        Err(err) => {
+            state.log_error(err);
+            return None;
+        }
     };
     if image_bytes.len() < IMG_HEADER {
         let msg = if ptr == 0 {
@@ -835,11 +1488,55 @@ fn load_image<'a>(state: &mut State, data: &'a [u8], ptr: u32, len: u32) -> Opti
         state.log_error(msg);
         return None;
     }
-    if image_bytes[0] != 0x22 {
-        state.log_error("invalid magic number");
-        return None;
+    match ParsedImage::parse(image_bytes) {
+        Ok(mut image) => {
+            image.remap = state.remap;
+            Some(image)
+        }
+        Err(err) => {
+            state.log_error(err);
+            None
+        }
     }
-    Some(image_bytes)
+}
+
+/// Draw the shape on the current draw target.
+///
+/// If a fill pattern is set, the shape fill is drawn using the pattern.
+fn draw_shape<S>(caller: &mut C, shape: &S, style: PrimitiveStyle<Gray4>)
+where
+    S: StyledDrawable<PrimitiveStyle<Gray4>, Color = Gray4, Output = ()>,
+{
+    let state = caller.data_mut();
+    let pattern = state.fill_pattern;
+    if let Some(canvas) = state.canvas.clone() {
+        let mut target = canvas.as_target(caller);
+        draw_patterned(shape, style, pattern, &mut target);
+    } else {
+        draw_patterned(shape, style, pattern, &mut state.frame);
+    }
+}
+
+fn draw_patterned<S, T>(
+    shape: &S,
+    style: PrimitiveStyle<Gray4>,
+    pattern: Option<Pattern>,
+    target: &mut T,
+) where
+    S: StyledDrawable<PrimitiveStyle<Gray4>, Color = Gray4, Output = ()>,
+    T: PatternTarget,
+{
+    let (Some(pattern), Some(fill_color)) = (pattern, style.fill_color) else {
+        never_fails(shape.draw_styled(&style, target));
+        return;
+    };
+    // The fill and the stroke are drawn separately
+    // so that the pattern is applied only to the fill.
+    let fill_style = PrimitiveStyle::with_fill(fill_color);
+    never_fails(shape.draw_styled(&fill_style, &mut Patterned::new(target, pattern)));
+    let mut stroke_style = style;
+    stroke_style.fill_color = None;
+    never_fails(shape.draw_styled(&stroke_style, target));
 }
 
 fn get_shape_style(fill_color: u32, stroke_color: u32, stroke_width: u32) -> PrimitiveStyle<Gray4> {
@@ -856,64 +1553,6 @@ fn get_shape_style(fill_color: u32, stroke_color: u32, stroke_width: u32) -> Pri
     style
 }
 
-/// Load mono font from the firefly format.
-fn parse_font(bytes: &'_ [u8]) -> Result<MonoFont<'_>, &'static str> {
-    if bytes.len() < 10 {
-        let msg = if bytes.is_empty() {
-            "font is empty: make sure you load it with a correct name"
-        } else {
-            "font is too short"
-        };
-        return Err(msg);
-    }
-
-    // read the header
-    let encoding_index = read_u8(bytes, 1);
-    let char_width = u32::from(read_u8(bytes, 2));
-    let char_height = u32::from(read_u8(bytes, 3));
-    let baseline = u32::from(read_u8(bytes, 4));
-    let image_width = u32::from(read_u16(bytes, 5));
-    let image = ImageRaw::new(&bytes[7..], image_width);
-
-    let glyph_mapping: &dyn mapping::GlyphMapping = match encoding_index {
-        0x0 => &mapping::ASCII,       // ASCII
-        0x1 => &mapping::ISO_8859_1,  // Latin-1, Western European.
-        0x2 => &mapping::ISO_8859_2,  // Latin-2, Central European.
-        0x3 => &mapping::ISO_8859_3,  // Latin-3, South European.
-        0x4 => &mapping::ISO_8859_4,  // Latin-4, North European.
-        0x5 => &mapping::ISO_8859_9,  // Latin-5, Turkish.
-        0x6 => &mapping::ISO_8859_10, // Latin-6, Nordic.
-        0x7 => &mapping::ISO_8859_13, // Latin-7, Baltic Rim.
-        0x8 => &mapping::ISO_8859_14, // Latin-8, Celtic.
-        0x9 => &mapping::ISO_8859_15, // Latin-9 (revised Latin-1).
-        0xa => &mapping::ISO_8859_16, // Latin-10: South-East European.
-        0xb => &mapping::ISO_8859_5,  // Latin/Cyrillic.
-        0xc => &mapping::ISO_8859_7,  // Latin/Greek.
-        0xd => &mapping::JIS_X0201,   // Japanese katakana (halfwidth).
-        _ => return Err("unknown mapping"),
-    };
-    let font = MonoFont {
-        image,
-        character_size: Size::new(char_width, char_height),
-        character_spacing: 0,
-        baseline,
-        strikethrough: DecorationDimensions::new(char_height / 2, 1),
-        underline: DecorationDimensions::new(baseline + 2, 1),
-        glyph_mapping,
-    };
-    Ok(font)
-}
-
-/// Read little-endian u8 from the slice at the given index.
-fn read_u8(bytes: &[u8], i: usize) -> u8 {
-    bytes[i]
-}
-
-/// Read little-endian u16 from the slice at the given index.
-fn read_u16(bytes: &[u8], i: usize) -> u16 {
-    u16::from_le_bytes([bytes[i], bytes[i + 1]])
-}
-
 fn parse_color(c: i32) -> Option<Gray4> {
     if c == 0 {
         None
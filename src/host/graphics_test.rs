@@ -1,5 +1,5 @@
 use crate::config::FullID;
-use crate::frame_buffer::FrameBuffer;
+use crate::frame_buffer::{FrameBuffer, HEIGHT};
 use crate::host::graphics::*;
 use crate::state::{NetHandler, State};
 use embedded_graphics::geometry::Point;
@@ -29,6 +29,51 @@ static IMG16: &[u8] = &[
     0xcd, 0xef, // row 4
 ];
 
+/// ASCII font with 1x1 glyphs, all pixels are set.
+static FONT: &[u8] = &[
+    // header
+    0x11, // magic number
+    0x00, // encoding: ASCII
+    0x01, // glyph width
+    0x01, // glyph height
+    0x00, // baseline
+    0x5f, // ┬ image width, 16 bit little-endian
+    0x00, // ┘
+    // glyphs, 1 bit per pixel
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+/// Proportional UTF-8 font with 1 pixel high glyphs for "a" and "ж".
+static UTF8_FONT: &[u8] = &[
+    // header
+    0x12, // magic number
+    0x01, // line height
+    0x00, // baseline
+    0x02, // ┬ number of glyphs, 16 bit little-endian
+    0x00, // ┘
+    0x03, // ┬ atlas width, 16 bit little-endian
+    0x00, // ┘
+    // glyph table: code point, atlas X, width, advance
+    0x61,
+    0x00,
+    0x00,
+    0x00,
+    0x00,
+    0x00,
+    0x01,
+    0x02, // a
+    0x36,
+    0x04,
+    0x00,
+    0x00,
+    0x01,
+    0x00,
+    0x02,
+    0x03, // ж
+    // atlas, 1 bit per pixel
+    0b_1110_0000,
+];
+
 #[test]
 fn test_clear_screen() {
     let mut store = make_store();
@@ -65,6 +110,29 @@ fn test_draw_line() {
             "......", // y=4
         ],
     );
+    let rows: Vec<_> = state.frame.dirty_rows().collect();
+    assert_eq!(rows, vec![1..4]);
+}
+
+/// Changing the palette must redraw the whole frame, not only the changed rows.
+#[test]
+fn test_set_color_marks_dirty() {
+    let mut store = make_store();
+    let set_color = wasmi::Func::wrap(&mut store, set_color);
+    let draw_point = wasmi::Func::wrap(&mut store, draw_point);
+
+    // Setting the same color doesn't change anything.
+    let inputs = wrap_input(&[1, 0x1a, 0x1c, 0x2c]);
+    set_color.call(&mut store, &inputs, &mut []).unwrap();
+    assert!(!store.data().frame.is_dirty());
+
+    let inputs = wrap_input(&[1, 0xff, 0x00, 0x00]);
+    set_color.call(&mut store, &inputs, &mut []).unwrap();
+    let inputs = wrap_input(&[3, 2, R]);
+    draw_point.call(&mut store, &inputs, &mut []).unwrap();
+
+    let rows: Vec<_> = store.data().frame.dirty_rows().collect();
+    assert_eq!(rows, vec![0..HEIGHT]);
 }
 
 #[test]
@@ -196,6 +264,79 @@ fn test_draw_rect_solid_w5() {
     );
 }
 
+#[test]
+fn test_draw_polyline() {
+    let mut store = make_store();
+    let points: Vec<u8> = [0i32, 0, 3, 0, 3, 2]
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .collect();
+    write_mem(&mut store, 5, &points);
+    let func = wasmi::Func::wrap(&mut store, draw_polyline);
+    let inputs = wrap_input(&[5, points.len() as _, R, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "RRRR..", // y=0
+            "...R..", // y=1
+            "...R..", // y=2
+            "......", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_draw_polygon() {
+    let mut store = make_store();
+    let points: Vec<u8> = [1i32, 1, 5, 1, 5, 4, 1, 4]
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .collect();
+    write_mem(&mut store, 5, &points);
+    let func = wasmi::Func::wrap(&mut store, draw_polygon);
+    let inputs = wrap_input(&[5, points.len() as _, P, N, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".PPPP.", // y=1
+            ".PPPP.", // y=2
+            ".PPPP.", // y=3
+            "......", // y=4
+        ],
+    );
+}
+
+#[test]
+fn test_draw_rect_fill_pattern() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, set_fill_pattern);
+    let inputs = wrap_input(&[0, 0, R]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    let func = wasmi::Func::wrap(&mut store, draw_rect);
+    let inputs = wrap_input(&[1, 0, 4, 2, P, N, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let inputs = wrap_input(&[1, 3, 4, 3, P, O, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            ".RPRP.", // y=0
+            ".PRPR.", // y=1
+            "......", // y=2
+            ".OOOO.", // y=3
+            ".OPRO.", // y=4
+            ".OOOO.", // y=5
+        ],
+    );
+}
+
 #[test]
 fn test_draw_rounded_rect() {
     let mut store = make_store();
@@ -309,6 +450,189 @@ fn test_draw_image() {
     );
 }
 
+#[test]
+fn test_draw_image_flip_x() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, draw_image_transformed);
+    write_mem(&mut store, 5, IMG16);
+    let inputs = wrap_input(&[5, IMG16.len() as _, 1, 2, 0b1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            "......", // y=1
+            ".ORP..", // y=2
+            ".DGgY.", // y=3
+            ".CbBd.", // y=4
+            ".◕◑◔W.", // y=5
+            "......", // y=6
+        ],
+    );
+}
+
+#[test]
+fn test_draw_image_flip_y() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, draw_image_transformed);
+    write_mem(&mut store, 5, IMG16);
+    let inputs = wrap_input(&[5, IMG16.len() as _, 1, 1, 0b10, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // Flipped both ways and partially out of the screen.
+    let inputs = wrap_input(&[5, IMG16.len() as _, -1, 6, 0b11, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".W◔◑◕.", // y=1
+            ".dBbC.", // y=2
+            ".YgGD.", // y=3
+            "..PRO.", // y=4
+            "......", // y=5
+            "◑◔W...", // y=6
+            "bBd...", // y=7
+            "GgY...", // y=8
+            "RP....", // y=9
+            "......", // y=10
+        ],
+    );
+}
+
+#[test]
+fn test_draw_sub_image_rotate() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, draw_sub_image_transformed);
+    write_mem(&mut store, 5, IMG16);
+    // The top-left 2x2 region rotated 90° clockwise and scaled x2.
+    let inputs = wrap_input(&[5, IMG16.len() as _, 1, 1, 0, 0, 2, 2, 0b0100, 2]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".YY...", // y=1
+            ".YY...", // y=2
+            ".ggPP.", // y=3
+            ".ggPP.", // y=4
+            "......", // y=5
+        ],
+    );
+}
+
+#[test]
+fn test_draw_image_remap() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 100];
+    mem[5..5 + IMG16.len()].copy_from_slice(IMG16);
+    // Swap colors 1 and 2, everything else stays the same.
+    let mut remap: Vec<u8> = (0..16).collect();
+    remap.swap(1, 2);
+    mem[50..66].copy_from_slice(&remap);
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, set_remap);
+    func.call(&mut store, &wrap_input(&[50]), &mut []).unwrap();
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    let inputs = wrap_input(&[5, IMG16.len() as _, 1, 2]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display_at(
+        Point::new(0, 1),
+        &state.frame,
+        &[
+            "......", // y=1
+            "..RPO.", // y=2
+            ".YgGD.", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_draw_image_1bpp() {
+    static IMG: &[u8] = &[
+        0x21, // magic number
+        0x01, // bits per pixel
+        0x08, // ┬ image width, 16 bit little-endian
+        0x00, // ┘
+        0xff, // transparency color
+        0x13, // palette: 0 is purple, 1 is orange
+        0b_1111_0000,
+        0b_0000_1111,
+    ];
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    write_mem(&mut store, 5, IMG);
+    let inputs = wrap_input(&[5, IMG.len() as _, 1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "..........", // y=0
+            ".OOOOPPPP.", // y=1
+            ".PPPPOOOO.", // y=2
+            "..........", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_draw_image_rle() {
+    static IMG: &[u8] = &[
+        0x23, // magic number
+        0x04, // ┬ image width, 16 bit little-endian
+        0x00, // ┘
+        0x03, // ┬ image height, 16 bit little-endian
+        0x00, // ┘
+        0x0f, // transparency color
+        0x82, // ┬ repeat purple 4 times
+        0x01, // ┘
+        0x02, // ┬ 3 literal pixels
+        0x23, // │
+        0x40, // ┘
+        0x80, // ┬ repeat transparent 2 times
+        0x0f, // ┘
+        0x02, // ┬ 3 literal pixels
+        0x34, // │
+        0x10, // ┘
+    ];
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    write_mem(&mut store, 5, IMG);
+    let inputs = wrap_input(&[5, IMG.len() as _, 1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".PPPP.", // y=1
+            ".ROY..", // y=2
+            "..OYP.", // y=3
+            "......", // y=4
+        ],
+    );
+
+    let func = wasmi::Func::wrap(&mut store, draw_sub_image);
+    let inputs = wrap_input(&[5, IMG.len() as _, 7, 1, 1, 1, 2, 2]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display_at(
+        Point::new(6, 0),
+        &state.frame,
+        &[
+            "....", // y=0
+            ".OY.", // y=1
+            ".OY.", // y=2
+            "....", // y=3
+        ],
+    );
+}
+
 #[test]
 fn test_draw_image_oob_left1() {
     let mut store = make_store();
@@ -616,6 +940,564 @@ fn test_draw_sub_image_oob_top() {
     );
 }
 
+#[test]
+fn test_draw_text_on_canvas() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 400];
+    mem[100..100 + FONT.len()].copy_from_slice(FONT);
+    mem[200..202].copy_from_slice(b"!!");
+    mem[300..300 + 4].copy_from_slice(&[0x22, 0x04, 0x00, 0xff]);
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, set_canvas);
+    let inputs = wrap_input(&[300, 4 + 8]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    let func = wasmi::Func::wrap(&mut store, draw_text);
+    let inputs = wrap_input(&[200, 2, 100, FONT.len() as _, 1, 1, R]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    assert_fb_empty(&store);
+    let state = store.data();
+    let memory = state.memory.unwrap();
+    let data = memory.data(&store);
+    assert_eq!(
+        &data[304..312],
+        &[0x00, 0x00, 0x20, 0x02, 0x00, 0x00, 0x00, 0x00]
+    );
+}
+
+#[test]
+fn test_draw_image_on_canvas() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 200];
+    mem[10..10 + IMG16.len()].copy_from_slice(IMG16);
+    mem[100..100 + 4].copy_from_slice(&[0x22, 0x04, 0x00, 0xff]);
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, set_canvas);
+    let inputs = wrap_input(&[100, 4 + 4]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    // The canvas is 4x2, so the bottom half of the image is cut out.
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    let inputs = wrap_input(&[10, IMG16.len() as _, 0, 0]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    assert_fb_empty(&store);
+    let state = store.data();
+    let memory = state.memory.unwrap();
+    let data = memory.data(&store);
+    assert_eq!(&data[104..108], &[0x10, 0x32, 0x54, 0x76]);
+}
+
+#[test]
+fn test_draw_canvas() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 200];
+    // 2x2 canvas, the even pixel is in the low nibble.
+    mem[100..106].copy_from_slice(&[0x22, 0x02, 0x00, 0xff, 0x21, 0x43]);
+    write_mem(&mut store, 0, &mem);
+
+    // Flip horizontally, scale x2, color 1 (purple) is transparent.
+    let func = wasmi::Func::wrap(&mut store, draw_canvas);
+    let inputs = wrap_input(&[100, 6, 1, 1, 0, 0, 0, 0, 0b1, 2, P]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".RR...", // y=1
+            ".RR...", // y=2
+            ".YYOO.", // y=3
+            ".YYOO.", // y=4
+            "......", // y=5
+        ],
+    );
+}
+
+#[test]
+fn test_draw_canvas_copy() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 200];
+    mem[100..106].copy_from_slice(&[0x22, 0x02, 0x00, 0xff, 0x21, 0x43]);
+    write_mem(&mut store, 0, &mem);
+
+    // No transformations, the pixels are copied as is.
+    let func = wasmi::Func::wrap(&mut store, draw_canvas);
+    // Not aligned with the frame buffer bytes.
+    let inputs = wrap_input(&[100, 6, 1, 1, 0, 0, 0, 0, 0, 1, -1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // Aligned.
+    let inputs = wrap_input(&[100, 6, 4, 1, 0, 0, 0, 0, 0, 1, -1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // Sub-region.
+    let inputs = wrap_input(&[100, 6, 0, 4, 1, 0, 1, 2, 0, 1, -1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // Partially out of the screen.
+    let inputs = wrap_input(&[100, 6, -1, 7, 0, 0, 0, 0, 0, 1, -1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".PR.PR", // y=1
+            ".OY.OY", // y=2
+            "......", // y=3
+            "R.....", // y=4
+            "Y.....", // y=5
+            "......", // y=6
+            "R.....", // y=7
+            "Y.....", // y=8
+        ],
+    );
+}
+
+#[test]
+fn test_draw_tilemap() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 200];
+    mem[100..100 + IMG16.len()].copy_from_slice(IMG16);
+    // The second tile, then the first tile flipped horizontally.
+    mem[150..154].copy_from_slice(&[0x01, 0x00, 0x00, 0x40]);
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, draw_tilemap);
+    let len = IMG16.len() as i32;
+    let inputs = wrap_input(&[100, len, 150, 4, 2, 2, 2, 1, 1, 4, 2, 0, 0]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // Scrolled by 1 pixel and clipped to a 2x1 area.
+    let inputs = wrap_input(&[100, len, 150, 4, 2, 2, 2, 1, 4, 2, 1, 1, 0]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".ROP..", // y=1
+            ".GDgY.", // y=2
+            "......", // y=3
+            ".OP...", // y=4
+            "......", // y=5
+        ],
+    );
+}
+
+#[test]
+fn test_draw_image_rle_truncated() {
+    static IMG: &[u8] = &[
+        0x23, // magic number
+        0x04, // ┬ image width, 16 bit little-endian
+        0x00, // ┘
+        0x03, // ┬ image height, 16 bit little-endian
+        0x00, // ┘
+        0x0f, // transparency color
+        0x82, // ┬ repeat purple 4 times
+        0x01, // ┘
+    ];
+    let mut store = make_store();
+    write_mem(&mut store, 5, IMG);
+    // Only the pixels that are present are drawn.
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    let inputs = wrap_input(&[5, IMG.len() as _, 1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // The missing pixels of the decoded region are black.
+    let func = wasmi::Func::wrap(&mut store, draw_image_transformed);
+    let inputs = wrap_input(&[5, IMG.len() as _, 1, 3, 0b1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".PPPP.", // y=1
+            "......", // y=2
+            ".PPPP.", // y=3
+            "......", // y=4
+            "......", // y=5
+            "......", // y=6
+        ],
+    );
+}
+
+#[test]
+fn test_draw_sub_image_rle_flip() {
+    static IMG: &[u8] = &[
+        0x23, // magic number
+        0x04, // ┬ image width, 16 bit little-endian
+        0x00, // ┘
+        0x03, // ┬ image height, 16 bit little-endian
+        0x00, // ┘
+        0x0f, // transparency color
+        0x82, // ┬ repeat purple 4 times
+        0x01, // ┘
+        0x02, // ┬ 3 literal pixels
+        0x23, // │
+        0x40, // ┘
+        0x80, // ┬ repeat transparent 2 times
+        0x0f, // ┘
+        0x02, // ┬ 3 literal pixels
+        0x34, // │
+        0x10, // ┘
+    ];
+    let mut store = make_store();
+    write_mem(&mut store, 5, IMG);
+    // Only the 2x2 region in the middle is decoded and flipped.
+    let func = wasmi::Func::wrap(&mut store, draw_sub_image_transformed);
+    let inputs = wrap_input(&[5, IMG.len() as _, 1, 1, 1, 1, 2, 2, 0b1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "....", // y=0
+            ".YO.", // y=1
+            ".YO.", // y=2
+            "....", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_draw_tilemap_rle() {
+    // The same pixels as in IMG16 but compressed.
+    static IMG: &[u8] = &[
+        0x23, // magic number
+        0x04, // ┬ image width, 16 bit little-endian
+        0x00, // ┘
+        0x04, // ┬ image height, 16 bit little-endian
+        0x00, // ┘
+        0xff, // transparency color
+        0x0f, // 16 literal pixels
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef,
+    ];
+    let mut store = make_store();
+    let mut mem = vec![0u8; 200];
+    mem[100..100 + IMG.len()].copy_from_slice(IMG);
+    // The second tile, then the first tile flipped horizontally.
+    mem[150..154].copy_from_slice(&[0x01, 0x00, 0x00, 0x40]);
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, draw_tilemap);
+    let len = IMG.len() as i32;
+    let inputs = wrap_input(&[100, len, 150, 4, 2, 2, 2, 1, 1, 4, 2, 0, 0]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".ROP..", // y=1
+            ".GDgY.", // y=2
+            "......", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_draw_tilemap_overflow() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 200];
+    mem[100..100 + IMG16.len()].copy_from_slice(IMG16);
+    mem[150..154].copy_from_slice(&[0x01, 0x00, 0x00, 0x40]);
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, draw_tilemap);
+    let len = IMG16.len() as i32;
+    // The area is clipped to the screen.
+    let inputs = wrap_input(&[100, len, 150, 4, 2, 2, 2, 1, 1, -1, -1, 0, 0]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // The area and the scroll are at the edge of i32.
+    let max = i32::MAX;
+    let inputs = wrap_input(&[100, len, 150, 4, 2, 2, 2, max, max, -1, -1, max, max]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let inputs = wrap_input(&[100, len, 150, 4, 2, 2, 2, 0, 0, 4, 4, max, max]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // The tiles are bigger than the tileset.
+    let inputs = wrap_input(&[100, len, 150, 4, -1, -1, 2, 0, 0, 4, 4, 0, 0]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".ROP..", // y=1
+            ".GDgY.", // y=2
+            "......", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_set_clip() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, set_clip);
+    let inputs = wrap_input(&[2, 1, 2, 2]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    let func = wasmi::Func::wrap(&mut store, draw_rect);
+    let inputs = wrap_input(&[0, 0, 6, 4, P, N, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            "..PP..", // y=1
+            "..PP..", // y=2
+            "......", // y=3
+        ],
+    );
+
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    write_mem(&mut store, 5, IMG16);
+    let inputs = wrap_input(&[5, IMG16.len() as _, 1, 0]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            "..gG..", // y=1
+            "..Bb..", // y=2
+            "......", // y=3
+        ],
+    );
+
+    let func = wasmi::Func::wrap(&mut store, unset_clip);
+    func.call(&mut store, &[], &mut []).unwrap();
+    let func = wasmi::Func::wrap(&mut store, draw_point);
+    let inputs = wrap_input(&[0, 0, P]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(&state.frame, &["P....."]);
+}
+
+#[test]
+fn test_set_clip_sub_image() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, set_clip);
+    let inputs = wrap_input(&[1, 1, 3, 2]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    let func = wasmi::Func::wrap(&mut store, draw_sub_image);
+    write_mem(&mut store, 5, IMG16);
+    let inputs = wrap_input(&[5, IMG16.len() as _, 0, 0, 1, 1, 3, 3]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // The sub-region is cut to fit into the image.
+    let inputs = wrap_input(&[5, IMG16.len() as _, 0, 0, 1, 1, -1, -1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".bC...", // y=1
+            ".◑◕...", // y=2
+            "......", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_set_offset() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, set_offset);
+    let inputs = wrap_input(&[2, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    let func = wasmi::Func::wrap(&mut store, draw_line);
+    let inputs = wrap_input(&[0, 0, 2, 0, R, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    write_mem(&mut store, 5, IMG16);
+    let inputs = wrap_input(&[5, IMG16.len() as _, -1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            "..RRR.", // y=1
+            "..PRO.", // y=2
+            ".YgGD.", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_get_pixel() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, draw_point);
+    let inputs = wrap_input(&[3, 2, O]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    let func = wasmi::Func::wrap(&mut store, get_pixel);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &wrap_input(&[3, 2]), &mut outputs)
+        .unwrap();
+    assert_eq!(outputs[0].i32(), Some(O));
+    func.call(&mut store, &wrap_input(&[2, 2]), &mut outputs)
+        .unwrap();
+    assert_eq!(outputs[0].i32(), Some(1));
+    func.call(&mut store, &wrap_input(&[-1, 2]), &mut outputs)
+        .unwrap();
+    assert_eq!(outputs[0].i32(), Some(0));
+}
+
+#[test]
+fn test_read_region() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, draw_image);
+    write_mem(&mut store, 5, IMG16);
+    let inputs = wrap_input(&[5, IMG16.len() as _, 1, 1]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+
+    // Reading the region back must produce the image pixels.
+    let func = wasmi::Func::wrap(&mut store, read_region);
+    let inputs = wrap_input(&[1, 1, 4, 4, 100, 8]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data();
+    let memory = state.memory.unwrap();
+    let data = memory.data(&store);
+    assert_eq!(&data[100..108], &IMG16[4..]);
+}
+
+/// Huge regions and regions beyond i32 must not panic.
+#[test]
+fn test_read_region_overflow() {
+    let mut store = make_store();
+    write_mem(&mut store, 100, &[0xaa; 4]);
+    let func = wasmi::Func::wrap(&mut store, read_region);
+
+    // The region is too big for the buffer, nothing is written.
+    let inputs = wrap_input(&[0, 0, -1, -1, 100, 4]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    // The buffer end is beyond the address space.
+    let inputs = wrap_input(&[0, 0, 2, 2, -1, 4]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let memory = store.data().memory.unwrap();
+    assert_eq!(&memory.data(&store)[100..104], &[0xaa; 4]);
+
+    // Pixels beyond i32 are out of bounds.
+    let inputs = wrap_input(&[i32::MAX - 1, 0, 4, 1, 100, 2]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let memory = store.data().memory.unwrap();
+    assert_eq!(&memory.data(&store)[100..104], &[0x00, 0x00, 0xaa, 0xaa]);
+}
+
+#[test]
+fn test_measure_text() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 300];
+    mem[100..100 + FONT.len()].copy_from_slice(FONT);
+    mem[200..203].copy_from_slice(b"hi!");
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, measure_text);
+    let inputs = wrap_input(&[200, 3, 100, FONT.len() as _]);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &inputs, &mut outputs).unwrap();
+    assert_eq!(outputs[0].i32(), Some((1 << 16) | 3));
+}
+
+#[test]
+fn test_draw_text_box() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 300];
+    mem[100..100 + FONT.len()].copy_from_slice(FONT);
+    let text = "ab cd efg";
+    mem[200..200 + text.len()].copy_from_slice(text.as_bytes());
+    write_mem(&mut store, 0, &mem);
+
+    // 3x2 box, aligned to the right.
+    let func = wasmi::Func::wrap(&mut store, draw_text_box);
+    let font_len = FONT.len() as i32;
+    let inputs = wrap_input(&[200, text.len() as _, 100, font_len, 1, 1, 3, 2, 2, 0, 0, R]);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &inputs, &mut outputs).unwrap();
+    assert_eq!(outputs[0].i32(), Some(6));
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            ".....", // y=0
+            "..RR.", // y=1
+            "..RR.", // y=2
+            ".....", // y=3
+        ],
+    );
+}
+
+#[test]
+fn test_draw_text_box_spacing() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 300];
+    mem[100..100 + FONT.len()].copy_from_slice(FONT);
+    let text = "ab cd";
+    mem[200..200 + text.len()].copy_from_slice(text.as_bytes());
+    let text2 = "a  b";
+    mem[220..220 + text2.len()].copy_from_slice(text2.as_bytes());
+    write_mem(&mut store, 0, &mem);
+
+    // The spacing after the last character doesn't need to fit into the box.
+    let func = wasmi::Func::wrap(&mut store, draw_text_box);
+    let font_len = FONT.len() as i32;
+    let inputs = wrap_input(&[200, text.len() as _, 100, font_len, 1, 1, 3, 2, 2, 0, 1, R]);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &inputs, &mut outputs).unwrap();
+    assert_eq!(outputs[0].i32(), Some(5));
+    // Trailing spaces don't shift the line aligned to the right.
+    let inputs = wrap_input(&[220, text2.len() as _, 100, font_len, 1, 4, 2, 2, 2, 0, 0, R]);
+    func.call(&mut store, &inputs, &mut outputs).unwrap();
+    assert_eq!(outputs[0].i32(), Some(4));
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            ".....", // y=0
+            ".R.R.", // y=1
+            ".R.R.", // y=2
+            ".....", // y=3
+            "..R..", // y=4
+            "..R..", // y=5
+            ".....", // y=6
+        ],
+    );
+}
+
+#[test]
+fn test_draw_text_utf8() {
+    let mut store = make_store();
+    let mut mem = vec![0u8; 300];
+    mem[100..100 + UTF8_FONT.len()].copy_from_slice(UTF8_FONT);
+    let text = "aж";
+    mem[200..200 + text.len()].copy_from_slice(text.as_bytes());
+    write_mem(&mut store, 0, &mem);
+
+    let func = wasmi::Func::wrap(&mut store, draw_text);
+    let font_len = UTF8_FONT.len() as i32;
+    let inputs = wrap_input(&[200, text.len() as _, 100, font_len, 1, 1, P]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data_mut();
+    check_display(
+        &state.frame,
+        &[
+            "......", // y=0
+            ".P.PP.", // y=1
+            "......", // y=2
+        ],
+    );
+
+    let func = wasmi::Func::wrap(&mut store, measure_text);
+    let inputs = wrap_input(&[200, text.len() as _, 100, font_len]);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &inputs, &mut outputs).unwrap();
+    assert_eq!(outputs[0].i32(), Some((1 << 16) | 5));
+}
+
 /// Place the given buffer into the linear wasm app memory.
 fn write_mem(store: &mut wasmi::Store<Box<State<'_>>>, addr: usize, buf: &[u8]) {
     let mem_type = wasmi::MemoryType::new(1, Some(1));
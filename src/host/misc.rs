@@ -4,6 +4,7 @@ use crate::state::{NetHandler, State};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use firefly_hal::{Device, Network};
+use firefly_types::serial;
 
 type C<'a, 'b> = wasmi::Caller<'a, Box<State<'b>>>;
 
@@ -60,6 +61,39 @@ pub(crate) fn set_seed(mut caller: C, seed: u32) {
     state.lock_seed = true;
 }
 
+/// Set how many times per second `update` should be called.
+///
+/// Supported values are 60 (default), 30, 20, and 15.
+/// Lower frame rate is good for slow-paced apps to save battery.
+pub(crate) fn set_fps(mut caller: C, fps: u32) {
+    let state = caller.data_mut();
+    state.called = "misc.set_fps";
+    if !matches!(fps, 60 | 30 | 20 | 15) {
+        state.log_error(HostError::InvalidFps(fps));
+        return;
+    }
+    state.fps = fps as u8;
+}
+
+/// Get the time since the app start, in nanoseconds.
+///
+/// The clock is monotonic and stays the same during a single frame,
+/// so it can be used to calculate the time passed since the previous update.
+/// In multiplayer, the time may differ between devices, so the game logic affecting
+/// the shared state should rely on [`get_frame`] instead.
+pub(crate) fn get_time(mut caller: C) -> u64 {
+    let state = caller.data_mut();
+    state.called = "misc.get_time";
+    state.uptime
+}
+
+/// Get the number of `update` calls since the app start, including the current one.
+pub(crate) fn get_frame(mut caller: C) -> u32 {
+    let state = caller.data_mut();
+    state.called = "misc.get_frame";
+    state.n_updates
+}
+
 /// Get a pseudo-random integer.
 ///
 /// Uses [xorshift] algorithm. It's very fast, easy to implement,
@@ -88,6 +122,64 @@ pub(crate) fn get_random(mut caller: C) -> u32 {
     x
 }
 
+/// Copy the last data payload received from the USB serial port into the buffer.
+///
+/// Returns how many bytes are written. If the buffer is too small
+/// to fit the whole payload, nothing is written and 0 is returned.
+pub(crate) fn load_data(mut caller: C, buf_ptr: u32, buf_len: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "misc.load_data";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let buf_ptr = buf_ptr as usize;
+    let buf_len = buf_len as usize;
+    let Some(buf_end) = buf_ptr.checked_add(buf_len) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    let Some(buf) = data.get_mut(buf_ptr..buf_end) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    let payload = &state.serial_data;
+    if payload.len() > buf.len() {
+        state.log_error("the buffer is not big enough to fit the data");
+        return 0;
+    }
+    buf[..payload.len()].copy_from_slice(payload);
+    payload.len() as u32
+}
+
+/// Send the data from the buffer into the USB serial port.
+///
+/// Used by the apps to reply to the data sent by desktop tools.
+pub(crate) fn send_data(mut caller: C, buf_ptr: u32, buf_len: u32) {
+    let state = caller.data_mut();
+    state.called = "misc.send_data";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let buf_ptr = buf_ptr as usize;
+    let buf_len = buf_len as usize;
+    let Some(buf_end) = buf_ptr.checked_add(buf_len) else {
+        state.log_error(HostError::OomPointer);
+        return;
+    };
+    let Some(buf) = data.get(buf_ptr..buf_end) else {
+        state.log_error(HostError::OomPointer);
+        return;
+    };
+    let resp = serial::Response::Data(buf.to_vec());
+    if let Err(err) = state.serial_send(resp) {
+        state.log_error(err);
+    }
+}
+
 /// Get the name of the given peer device.
 ///
 /// The buffer must be at least 16 bytes. Returns how many bytes are written.
@@ -37,6 +37,40 @@ fn test_set_seed() {
     assert_eq!(state.seed, 131415)
 }
 
+#[test]
+fn test_set_fps() {
+    let mut store = make_store();
+    let func = wasmi::Func::wrap(&mut store, set_fps);
+    let inputs = wrap_input(&[30]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data();
+    assert_eq!(state.fps, 30);
+
+    // unsupported frame rate is ignored
+    let inputs = wrap_input(&[40]);
+    func.call(&mut store, &inputs, &mut []).unwrap();
+    let state = store.data();
+    assert_eq!(state.fps, 30);
+}
+
+#[test]
+fn test_get_time() {
+    let mut store = make_store();
+    let state = store.data_mut();
+    state.uptime = 1_500_000_000;
+    state.n_updates = 90;
+
+    let func = wasmi::Func::wrap(&mut store, get_time);
+    let mut outputs = vec![wasmi::Val::I64(0)];
+    func.call(&mut store, &[], &mut outputs).unwrap();
+    assert_eq!(outputs[0].i64(), Some(1_500_000_000));
+
+    let func = wasmi::Func::wrap(&mut store, get_frame);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &[], &mut outputs).unwrap();
+    assert_eq!(outputs[0].i32(), Some(90));
+}
+
 #[test]
 fn test_get_random() {
     let mut store = make_store();
@@ -74,6 +108,41 @@ fn test_get_name() {
     assert_eq!(&data[10..15], b"hello");
 }
 
+#[test]
+fn test_load_data() {
+    let mut store = make_store();
+    let state = store.data_mut();
+    state.serial_data = b"hello".to_vec();
+    let memory = make_memory(&mut store);
+
+    let func = wasmi::Func::wrap(&mut store, load_data);
+    let inputs = wrap_input(&[10, 8]);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &inputs, &mut outputs).unwrap();
+
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].i32(), Some(5));
+    let data = memory.data_mut(&mut store);
+    assert_eq!(&data[10..15], b"hello");
+}
+
+#[test]
+fn test_load_data_small_buf() {
+    let mut store = make_store();
+    let state = store.data_mut();
+    state.serial_data = b"hello".to_vec();
+    let memory = make_memory(&mut store);
+
+    let func = wasmi::Func::wrap(&mut store, load_data);
+    let inputs = wrap_input(&[10, 3]);
+    let mut outputs = wrap_input(&[0]);
+    func.call(&mut store, &inputs, &mut outputs).unwrap();
+
+    assert_eq!(outputs[0].i32(), Some(0));
+    let data = memory.data_mut(&mut store);
+    assert_eq!(&data[10..15], &[0; 5]);
+}
+
 fn wrap_input(a: &[i32]) -> Vec<wasmi::Val> {
     let mut res = Vec::new();
     for el in a {
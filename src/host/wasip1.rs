@@ -30,9 +30,38 @@ pub(crate) fn environ_sizes_get(mut caller: C, offset0: i32, offset1: i32) -> i3
     0
 }
 
-pub(crate) fn clock_time_get(mut caller: C, _id: i32, _precision: i64, _offset0: i32) -> i32 {
+/// WASI errno: bad address.
+const ERRNO_FAULT: i32 = 21;
+/// WASI errno: invalid argument.
+const ERRNO_INVAL: i32 = 28;
+
+/// Write the current time in nanoseconds as u64 at the given address.
+///
+/// The device doesn't have a real-time clock, so all clocks, including
+/// the realtime one, count the time since the app start.
+pub(crate) fn clock_time_get(mut caller: C, id: i32, _precision: i64, offset0: i32) -> i32 {
     let state = caller.data_mut();
     state.called = "wasi_snapshot_preview1.clock_time_get";
+    // 0: realtime, 1: monotonic, 2: process CPU time, 3: thread CPU time.
+    if !(0..=3).contains(&id) {
+        return ERRNO_INVAL;
+    }
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return ERRNO_FAULT;
+    };
+    let now = state.now_ns();
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let offset0 = offset0 as usize;
+    let Some(end) = offset0.checked_add(8) else {
+        state.log_error(HostError::OomPointer);
+        return ERRNO_FAULT;
+    };
+    let Some(buf) = data.get_mut(offset0..end) else {
+        state.log_error(HostError::OomPointer);
+        return ERRNO_FAULT;
+    };
+    buf.copy_from_slice(&now.to_le_bytes());
     0
 }
 pub(crate) fn fd_close(_caller: C, _fd: i32) -> i32 {
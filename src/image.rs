@@ -1,23 +1,373 @@
-use crate::{FrameBuffer, HEIGHT, WIDTH};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
 
 const BPP: usize = 4;
 const PPB: usize = 2;
 
+/// The magic number of 4 BPP images.
+///
+/// Header: magic, width (u16 little-endian), transparency color.
+const MAGIC_4BPP: u8 = 0x22;
+
+/// The magic number of 1 BPP and 2 BPP images.
+///
+/// Header: magic, BPP, width (u16 little-endian), transparency color, palette.
+/// The palette maps each image color to a frame palette color, 4 bits per color,
+/// so it takes 1 byte for 1 BPP and 2 bytes for 2 BPP.
+const MAGIC_LOW_BPP: u8 = 0x21;
+
+/// The magic number of RLE-compressed 4 BPP images.
+///
+/// Header: magic, width (u16 little-endian), height (u16 little-endian),
+/// transparency color. The pixel data is a sequence of runs, each starting
+/// with a control byte `n`:
+///
+/// * Below 128, it is followed by `n + 1` literal pixels, 2 pixels per byte.
+///   If the number of pixels is odd, the low nibble of the last byte is unused.
+/// * 128 or above, it is followed by a single byte with the color in the low nibble
+///   that is repeated `n - 126` times.
+///
+/// Runs are not aligned to rows, a run can span multiple rows.
+const MAGIC_RLE: u8 = 0x23;
+
+/// A buffer of tightly packed 4 BPP pixels that images can be drawn on.
+///
+/// Even pixels are stored in the low nibble of a byte and odd pixels in the high one.
+pub trait PackedTarget {
+    /// The width of the buffer in pixels.
+    fn width(&self) -> usize;
+
+    /// The height of the buffer in pixels.
+    fn height(&self) -> usize;
+
+    /// The raw pixel data.
+    fn data_mut(&mut self) -> &mut [u8];
+
+    /// Set the color of a single pixel and mark its row as changed.
+    ///
+    /// Out-of-bounds pixels are ignored.
+    fn set_pixel(&mut self, point: Point, luma: u8);
+
+    /// Mark the given rows as changed after modifying [`PackedTarget::data_mut`].
+    fn mark_rows_dirty(&mut self, rows: Range<usize>);
+
+    /// If true, only a part of the buffer can be drawn on.
+    ///
+    /// The pixels outside of the clip area are ignored by [`PackedTarget::set_pixel`]
+    /// but the raw data returned by [`PackedTarget::data_mut`] is not restricted.
+    fn is_clipped(&self) -> bool;
+
+    /// The area that can be drawn on, always inside of the buffer bounds.
+    fn clip(&self) -> Rectangle;
+}
+
+/// A table that maps each of the 16 image colors to another color.
+pub type Remap = [u8; 16];
+
 pub struct ParsedImage<'a> {
+    /// Pixel data without the header.
     pub bytes: &'a [u8],
     pub width: u32,
+    /// Bits per pixel: 1, 2, or 4.
+    pub bpp: u8,
+    /// If true, the pixel data is RLE-compressed 4 BPP.
+    pub compressed: bool,
+    height: u32,
+    /// Maps image colors to frame palette colors, for images with less than 4 BPP.
+    pub palette: [u8; 4],
+    /// The transparent color (in the frame palette). Values above 15 mean no transparency.
     pub transp: u8,
     pub sub: Option<Rectangle>,
+    /// If set, the image colors are replaced when drawing.
+    ///
+    /// The transparency color is checked before the replacement.
+    pub remap: Option<Remap>,
 }
 
-impl ParsedImage<'_> {
-    pub fn render(&self, point: Point, frame: &mut FrameBuffer) {
-        if let Some(sub) = self.sub {
-            self.draw_sub_fast(point, sub, frame);
+impl<'a> ParsedImage<'a> {
+    /// Parse the image header and validate the image size.
+    pub fn parse(raw: &'a [u8]) -> Result<Self, &'static str> {
+        let Some(&magic) = raw.first() else {
+            return Err("image file is empty");
+        };
+        if magic == MAGIC_RLE {
+            return Self::parse_rle(raw);
+        }
+        let (bpp, header) = match magic {
+            MAGIC_4BPP => (4, 4),
+            MAGIC_LOW_BPP => {
+                let Some(&bpp) = raw.get(1) else {
+                    return Err("image file is too small");
+                };
+                match bpp {
+                    1 => (1, 6),
+                    2 => (2, 7),
+                    _ => return Err("unsupported bits per pixel"),
+                }
+            }
+            _ => return Err("invalid magic number"),
+        };
+        let Some(raw_header) = raw.get(..header) else {
+            return Err("image file is too small");
+        };
+        // The low BPP header has an extra byte for BPP after the magic number.
+        let (width, transp, raw_palette) = if bpp == 4 {
+            (u16::from_le_bytes([raw[1], raw[2]]), raw[3], &[][..])
         } else {
-            self.draw_fast(point, frame);
+            (
+                u16::from_le_bytes([raw[2], raw[3]]),
+                raw[4],
+                &raw_header[5..],
+            )
+        };
+        let width = u32::from(width);
+        if width == 0 {
+            return Err("image has zero width");
+        }
+        let bytes = &raw[header..];
+        let ppb = 8 / u32::from(bpp);
+        if !(bytes.len() as u32 * ppb).is_multiple_of(width) {
+            return Err("the image has invalid width");
+        }
+        let mut palette = [0u8; 4];
+        for (i, color) in palette.iter_mut().take(1 << bpp).enumerate() {
+            let byte = raw_palette.get(i / 2).copied().unwrap_or_default();
+            *color = if i.is_multiple_of(2) {
+                byte >> 4
+            } else {
+                byte & 0xf
+            };
+        }
+        Ok(Self {
+            bytes,
+            width,
+            bpp,
+            compressed: false,
+            height: bytes.len() as u32 * ppb / width,
+            palette,
+            transp,
+            sub: None,
+            remap: None,
+        })
+    }
+
+    /// Parse the header of an RLE-compressed image.
+    ///
+    /// The pixel data is not validated here because that would require decoding
+    /// the whole image on every draw. Instead, decoding stops at the end of the data,
+    /// so the pixels missing in a truncated image are just not drawn.
+    fn parse_rle(raw: &'a [u8]) -> Result<Self, &'static str> {
+        const HEADER: usize = 6;
+        if raw.len() < HEADER {
+            return Err("image file is too small");
+        }
+        let width = u32::from(u16::from_le_bytes([raw[1], raw[2]]));
+        let height = u32::from(u16::from_le_bytes([raw[3], raw[4]]));
+        if width == 0 {
+            return Err("image has zero width");
+        }
+        Ok(Self {
+            bytes: &raw[HEADER..],
+            width,
+            bpp: 4,
+            compressed: true,
+            height,
+            palette: [0; 4],
+            transp: raw[5],
+            sub: None,
+            remap: None,
+        })
+    }
+
+    /// The image height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Decode the compressed image into raw 4 BPP pixel data.
+    ///
+    /// Returns [`None`] if the image is not compressed.
+    ///
+    /// Use it together with [`ParsedImage::decoded`] when drawing many parts
+    /// of the same image, so that the image is decoded only once.
+    pub fn decompress(&self) -> Option<Vec<u8>> {
+        if !self.compressed {
+            return None;
+        }
+        let full = Rectangle::new(Point::zero(), Size::new(self.width, self.height));
+        Some(self.decompress_region(full))
+    }
+
+    /// Decode a region of the compressed image into raw 4 BPP pixel data.
+    ///
+    /// The region must be inside of the image. The result is as big as the region
+    /// and only the pixels up to the last row of the region are decoded.
+    /// The pixels missing in a truncated image are left black.
+    fn decompress_region(&self, region: Rectangle) -> Vec<u8> {
+        let width = self.width as usize;
+        let left = region.top_left.x as usize;
+        let top = region.top_left.y as usize;
+        let region_width = region.size.width as usize;
+        let region_height = region.size.height as usize;
+        let right = left + region_width;
+        let end = (top + region_height) * width;
+        let mut raw = vec![0u8; (region_width * region_height).div_ceil(PPB)];
+        decode_rle(self.bytes, |i, luma| {
+            if i >= end {
+                return false;
+            }
+            let x = i % width;
+            let y = i / width;
+            if y >= top && x >= left && x < right {
+                let j = (y - top) * region_width + (x - left);
+                raw[j / PPB] |= if j.is_multiple_of(PPB) {
+                    luma << 4
+                } else {
+                    luma
+                };
+            }
+            true
+        });
+        raw
+    }
+
+    /// The same image but with the pixel data decoded by [`ParsedImage::decompress`].
+    pub fn decoded<'b>(&'b self, raw: &'b [u8]) -> ParsedImage<'b> {
+        ParsedImage {
+            bytes: raw,
+            compressed: false,
+            ..*self
+        }
+    }
+
+    /// Draw the compressed image, decoding it on the fly.
+    ///
+    /// Only the pixels up to the last visible row are decoded.
+    fn draw_rle<T: PackedTarget + ?Sized>(&self, point: Point, target: &mut T) {
+        let full = Rectangle::new(Point::zero(), Size::new(self.width, self.height));
+        let sub = match self.sub {
+            Some(sub) => sub.intersection(&full),
+            None => full,
+        };
+        if sub.is_zero_sized() {
+            return;
+        }
+        let width = self.width as usize;
+        let left = sub.top_left.x as usize;
+        let top = sub.top_left.y as usize;
+        let right = left + sub.size.width as usize;
+        // The rows below the clip area are not visible, so they are not decoded.
+        let clip = target.clip();
+        let clip_bottom = i64::from(clip.top_left.y) + i64::from(clip.size.height);
+        let height = (clip_bottom - i64::from(point.y)).clamp(0, i64::from(sub.size.height));
+        let end = (top + height as usize) * width;
+        decode_rle(self.bytes, |i, luma| {
+            if i >= end {
+                return false;
+            }
+            let x = i % width;
+            let y = i / width;
+            if y >= top && x >= left && x < right && luma != self.transp {
+                let px = point.x + (x - left) as i32;
+                let py = point.y + (y - top) as i32;
+                target.set_pixel(Point::new(px, py), self.map_color(luma));
+            }
+            true
+        });
+    }
+
+    /// Get the color of the image pixel in the frame palette.
+    ///
+    /// The remap table is not applied.
+    fn get_pixel(&self, x: u32, y: u32) -> u8 {
+        let bpp = usize::from(self.bpp);
+        let ppb = 8 / bpp;
+        let offset = (y * self.width + x) as usize;
+        let byte = self.bytes[offset / ppb];
+        // The first pixel is stored in the highest bits.
+        let shift = 8 - bpp * (1 + offset % ppb);
+        let luma = (byte >> shift) & ((1 << bpp) - 1);
+        if bpp == BPP {
+            luma
+        } else {
+            self.palette[usize::from(luma)]
+        }
+    }
+
+    pub fn render<T: PackedTarget + ?Sized>(&self, point: Point, target: &mut T) {
+        // The fast paths are implemented only for uncompressed 4 BPP.
+        // Some of them write directly into the buffer ignoring the clip area,
+        // so if there is one, the image is cut to fit into it first.
+        if self.compressed {
+            self.draw_rle(point, target);
+        } else if usize::from(self.bpp) != BPP {
+            let transform = Transform::from_flags(0, 1);
+            self.render_transformed(point, transform, target);
+        } else if target.is_clipped() {
+            self.draw_clipped(point, target);
+        } else if let Some(sub) = self.sub {
+            self.draw_sub_fast(point, sub, target);
+        } else {
+            self.draw_fast(point, target);
+        }
+    }
+
+    /// Draw the image (or its sub-region) flipped, rotated, and/or scaled.
+    pub fn render_transformed<T>(&self, point: Point, transform: Transform, target: &mut T)
+    where
+        T: PackedTarget + ?Sized,
+    {
+        let full = Rectangle::new(Point::zero(), Size::new(self.width, self.height()));
+        // The sub-region is clipped to fit into the image.
+        let sub = match self.sub {
+            Some(sub) => sub.intersection(&full),
+            None => full,
+        };
+        if sub.is_zero_sized() {
+            return;
+        }
+        // Transformations need random access to pixels, so the compressed image
+        // is decoded first. Only the drawn region, to not waste memory on the rest.
+        if self.compressed {
+            let raw = self.decompress_region(sub);
+            let image = ParsedImage {
+                bytes: &raw,
+                width: sub.size.width,
+                height: sub.size.height,
+                compressed: false,
+                sub: None,
+                ..*self
+            };
+            image.render_transformed(point, transform, target);
+            return;
+        }
+        // Flips without rotation and scaling are drawn row by row.
+        // Like other fast paths, it writes directly into the buffer ignoring the clip area.
+        let only_flip = transform.rotate == 0 && transform.scale == 1;
+        if only_flip && usize::from(self.bpp) == BPP && !target.is_clipped() {
+            self.draw_flipped_fast(point, sub, transform.flip_x, transform.flip_y, target);
+            return;
+        }
+        let transp = if self.transp > 15 {
+            None
+        } else {
+            Some(self.transp)
+        };
+        let left = sub.top_left.x as u32;
+        let top = sub.top_left.y as u32;
+        let get = |x: u32, y: u32| self.get_pixel(left + x, top + y);
+        let remap = self.remap.as_ref();
+        draw_transformed(target, point, sub.size, transform, transp, remap, get);
+    }
+
+    /// Apply the color remap table (if any) to the image color.
+    fn map_color(&self, luma: u8) -> u8 {
+        match &self.remap {
+            Some(remap) => remap[usize::from(luma)],
+            None => luma,
         }
     }
 
@@ -25,7 +375,9 @@ impl ParsedImage<'_> {
     ///
     /// Avoids going through embedded-graphics machinery and instead
     /// iterates over image bytes directly.
-    fn draw_fast(&self, point: Point, frame: &mut FrameBuffer) {
+    fn draw_fast<T: PackedTarget + ?Sized>(&self, point: Point, frame: &mut T) {
+        let target_width = frame.width() as i32;
+        let target_height = frame.height() as i32;
         let mut p = point;
         let mut image = self.bytes;
 
@@ -42,8 +394,8 @@ impl ParsedImage<'_> {
         // Cut the bottom out-of-bounds part of the image.
         let height = (image.len() * PPB) as i32 / self.width as i32;
         let bottom_y = p.y + height;
-        if bottom_y > HEIGHT as i32 {
-            let new_height = height - (bottom_y - HEIGHT as i32);
+        if bottom_y > target_height {
+            let new_height = height - (bottom_y - target_height);
             let end_i = (new_height * self.width as i32) as usize / PPB;
             let Some(sub_image) = image.get(..end_i) else {
                 return;
@@ -55,10 +407,10 @@ impl ParsedImage<'_> {
 
         // Skip the right out-of-bounds part of the image.
         let mut right_x = point.x + self.width as i32;
-        if right_x > WIDTH as i32 {
-            let skip_px = (right_x - WIDTH as i32) as usize;
+        if right_x > target_width {
+            let skip_px = (right_x - target_width) as usize;
             skip = skip_px / PPB;
-            right_x = WIDTH as i32 + (skip_px % PPB) as i32;
+            right_x = target_width + (skip_px % PPB) as i32;
         }
 
         // Skip the left out-of-bounds part of the image.
@@ -69,28 +421,40 @@ impl ParsedImage<'_> {
             left_x = -((skip_px % PPB) as i32);
         }
 
-        // Check if the image bytes are aligned with frame buffer bytes
+        // Check if the image bytes are aligned with target buffer bytes
         // and no image bytes are cut in half.
-        let is_aligned = point.x % 2 == 0 && left_x % 2 == 0 && right_x % 2 == 0;
+        let is_aligned =
+            point.x % 2 == 0 && left_x % 2 == 0 && right_x % 2 == 0 && target_width % 2 == 0;
 
         // A faster implementation for when
         // no transparency is used and the image is aligned.
         if self.transp > 15 && is_aligned && p.x >= 0 {
             let line_bytes = (right_x - left_x) as usize / PPB;
-            let mut target = &mut frame.data[..];
-            let target_offset = (p.y as usize * WIDTH + p.x as usize) / PPB;
+            let row_bytes = target_width as usize / PPB;
+            let top = p.y as usize;
+            let height = image.len() * PPB / self.width as usize;
+            frame.mark_rows_dirty(top..top + height);
+            let mut target = frame.data_mut();
+            let target_offset = (p.y as usize * target_width as usize + p.x as usize) / PPB;
             target = &mut target[target_offset..];
             while !image.is_empty() {
                 for (i, byte) in image[..line_bytes].iter().enumerate() {
-                    target[i] = byte.rotate_right(4);
+                    target[i] = match &self.remap {
+                        Some(remap) => {
+                            let left = remap[usize::from(byte >> 4)];
+                            let right = remap[usize::from(byte & 0b1111)];
+                            left | (right << 4)
+                        }
+                        None => byte.rotate_right(4),
+                    };
                 }
-                if target.len() < WIDTH / PPB {
+                if target.len() < row_bytes {
                     break;
                 }
-                target = &mut target[WIDTH / PPB..];
+                target = &mut target[row_bytes..];
                 image = &image[self.width as usize / PPB..]
             }
-            frame.dirty = true;
+            return;
         }
 
         let mut i = 0;
@@ -100,7 +464,7 @@ impl ParsedImage<'_> {
                 byte = byte.rotate_left(BPP as u32);
                 let luma = byte & 0b1111;
                 if luma != self.transp {
-                    frame.set_pixel(p, luma);
+                    frame.set_pixel(p, self.map_color(luma));
                 };
                 p.x += 1;
                 if p.x >= right_x {
@@ -111,10 +475,14 @@ impl ParsedImage<'_> {
             }
             i += 1;
         }
-        frame.dirty = true;
     }
 
-    fn draw_sub_fast(&self, point: Point, sub: Rectangle, frame: &mut FrameBuffer) {
+    fn draw_sub_fast<T>(&self, point: Point, sub: Rectangle, frame: &mut T)
+    where
+        T: PackedTarget + ?Sized,
+    {
+        let target_width = frame.width() as i32;
+        let target_height = frame.height() as i32;
         let mut p = point;
         let mut top = sub.top_left.y;
         let mut left = sub.top_left.x;
@@ -134,7 +502,7 @@ impl ParsedImage<'_> {
         if height > max_height {
             height = max_height;
         }
-        let oob_bottom = (p.y + height) - HEIGHT as i32;
+        let oob_bottom = (p.y + height) - target_height;
         if oob_bottom > 0 {
             height -= oob_bottom;
             if height <= 0 {
@@ -158,7 +526,7 @@ impl ParsedImage<'_> {
         if width > max_width {
             width = max_width;
         }
-        let oob_right = (p.y + width) - WIDTH as i32;
+        let oob_right = (p.x + width) - target_width;
         if oob_right > 0 {
             width -= oob_right;
             if width <= 0 {
@@ -180,10 +548,365 @@ impl ParsedImage<'_> {
                 if luma != self.transp {
                     let fx = p.x + (ix - left);
                     let fy = p.y + (iy - top);
-                    frame.set_pixel(Point::new(fx, fy), luma);
+                    frame.set_pixel(Point::new(fx, fy), self.map_color(luma));
+                }
+            }
+        }
+    }
+
+    /// Draw only the part of the 4 BPP image (or its sub-region) inside of the clip area.
+    ///
+    /// The visible region is calculated once and then drawn using the fast path
+    /// instead of checking every pixel of the image against the clip area.
+    fn draw_clipped<T>(&self, point: Point, target: &mut T)
+    where
+        T: PackedTarget + ?Sized,
+    {
+        // All the math is in i64 so that huge regions can't overflow.
+        let (sub_x, sub_y, sub_width, sub_height) = match self.sub {
+            Some(sub) => (
+                i64::from(sub.top_left.x),
+                i64::from(sub.top_left.y),
+                i64::from(sub.size.width),
+                i64::from(sub.size.height),
+            ),
+            None => (0, 0, i64::from(self.width), i64::from(self.height)),
+        };
+        // Cut the sub-region to fit into the image.
+        let sub_left = sub_x.max(0);
+        let sub_top = sub_y.max(0);
+        let sub_right = (sub_x + sub_width).min(i64::from(self.width));
+        let sub_bottom = (sub_y + sub_height).min(i64::from(self.height));
+        let x = i64::from(point.x) + sub_left - sub_x;
+        let y = i64::from(point.y) + sub_top - sub_y;
+
+        // Cut the region to fit into the clip area.
+        let clip = target.clip();
+        let clip_x = i64::from(clip.top_left.x);
+        let clip_y = i64::from(clip.top_left.y);
+        let left = x.max(clip_x);
+        let top = y.max(clip_y);
+        let right = (x + sub_right - sub_left).min(clip_x + i64::from(clip.size.width));
+        let bottom = (y + sub_bottom - sub_top).min(clip_y + i64::from(clip.size.height));
+        if left >= right || top >= bottom {
+            return;
+        }
+        let sub = Rectangle::new(
+            Point::new((sub_left + left - x) as i32, (sub_top + top - y) as i32),
+            Size::new((right - left) as u32, (bottom - top) as u32),
+        );
+        let point = Point::new(left as i32, top as i32);
+        self.draw_sub_fast(point, sub, target);
+    }
+
+    /// Faster implementation of drawing a flipped region of a 4 BPP image.
+    ///
+    /// Instead of mapping each pixel through [`Transform`], walks the image rows
+    /// (backwards if flipped vertically) and copies the pixel nibbles straight into
+    /// the target buffer (right to left if flipped horizontally).
+    fn draw_flipped_fast<T>(
+        &self,
+        point: Point,
+        sub: Rectangle,
+        flip_x: bool,
+        flip_y: bool,
+        frame: &mut T,
+    ) where
+        T: PackedTarget + ?Sized,
+    {
+        // The sub-region is already clipped to fit into the image.
+        let width = sub.size.width as i32;
+        let height = sub.size.height as i32;
+        let target_width = frame.width();
+        let left = point.x.max(0);
+        let top = point.y.max(0);
+        let right = point.x.saturating_add(width).min(target_width as i32);
+        let bottom = point.y.saturating_add(height).min(frame.height() as i32);
+        if left >= right || top >= bottom {
+            return;
+        }
+        frame.mark_rows_dirty(top as usize..bottom as usize);
+        let data = frame.data_mut();
+        for fy in top..bottom {
+            let v = fy - point.y;
+            let iy = sub.top_left.y + if flip_y { height - 1 - v } else { v };
+            let image_row = iy as usize * self.width as usize;
+            let frame_row = fy as usize * target_width;
+            for fx in left..right {
+                let u = fx - point.x;
+                let ix = sub.top_left.x + if flip_x { width - 1 - u } else { u };
+                // In the image, the first pixel is stored in the high nibble.
+                let offset = image_row + ix as usize;
+                let shift = if offset.is_multiple_of(PPB) { BPP } else { 0 };
+                let luma = (self.bytes[offset / PPB] >> shift) & 0b1111;
+                if luma == self.transp {
+                    continue;
                 }
+                // In the target, the first pixel is stored in the low nibble.
+                let offset = frame_row + fx as usize;
+                let shift = if offset.is_multiple_of(PPB) { 0 } else { BPP };
+                let byte = &mut data[offset / PPB];
+                *byte = (*byte & !(0b1111 << shift)) | (self.map_color(luma) << shift);
+            }
+        }
+    }
+}
+
+/// Decode RLE-compressed pixels, calling `f` with the index and color of each pixel.
+///
+/// Decoding stops when `f` returns false. Returns the number of decoded pixels
+/// or [`None`] if the data is truncated.
+fn decode_rle<F>(bytes: &[u8], mut f: F) -> Option<usize>
+where
+    F: FnMut(usize, u8) -> bool,
+{
+    let mut i = 0;
+    let mut pos = 0;
+    while let Some(&ctrl) = bytes.get(pos) {
+        pos += 1;
+        if ctrl < 128 {
+            let n = usize::from(ctrl) + 1;
+            let n_bytes = n.div_ceil(PPB);
+            let raw = bytes.get(pos..pos + n_bytes)?;
+            pos += n_bytes;
+            for j in 0..n {
+                let byte = raw[j / PPB];
+                let luma = if j.is_multiple_of(PPB) {
+                    byte >> 4
+                } else {
+                    byte & 0b1111
+                };
+                if !f(i, luma) {
+                    return Some(i);
+                }
+                i += 1;
+            }
+        } else {
+            let n = usize::from(ctrl) - 126;
+            let luma = bytes.get(pos)? & 0b1111;
+            pos += 1;
+            for _ in 0..n {
+                if !f(i, luma) {
+                    return Some(i);
+                }
+                i += 1;
+            }
+        }
+    }
+    Some(i)
+}
+
+/// Flip, rotation, and scale applied to a drawn region of pixels.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Transform {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Clockwise rotation in 90° steps, from 0 to 3.
+    pub rotate: u8,
+    /// Integer scale factor, at least 1.
+    pub scale: u32,
+}
+
+impl Transform {
+    /// Parse the transformation from host function arguments.
+    ///
+    /// In flags, bit 0 is horizontal flip, bit 1 is vertical flip,
+    /// and bits 2-3 are the clockwise rotation in 90° steps.
+    /// The flip is applied before the rotation. Zero scale is the same as 1.
+    pub fn from_flags(flags: u32, scale: u32) -> Self {
+        Self {
+            flip_x: flags & 0b1 != 0,
+            flip_y: flags & 0b10 != 0,
+            rotate: ((flags >> 2) & 0b11) as u8,
+            scale: scale.max(1),
+        }
+    }
+
+    /// True if the pixels are drawn as is.
+    pub fn is_identity(&self) -> bool {
+        !self.flip_x && !self.flip_y && self.rotate == 0 && self.scale == 1
+    }
+
+    /// The size of the region after the transformation.
+    fn apply_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = if self.rotate % 2 == 0 {
+            (width, height)
+        } else {
+            (height, width)
+        };
+        let width = width.saturating_mul(self.scale);
+        let height = height.saturating_mul(self.scale);
+        (width, height)
+    }
+
+    /// Map a point on the transformed (but not scaled) region back to the source region.
+    fn source(&self, u: u32, v: u32, width: u32, height: u32) -> (u32, u32) {
+        let (mut x, mut y) = match self.rotate {
+            0 => (u, v),
+            1 => (v, height - 1 - u),
+            2 => (width - 1 - u, height - 1 - v),
+            _ => (width - 1 - v, u),
+        };
+        if self.flip_x {
+            x = width - 1 - x;
+        }
+        if self.flip_y {
+            y = height - 1 - y;
+        }
+        (x, y)
+    }
+}
+
+/// Draw a transformed region of pixels.
+///
+/// The source pixels are read using `get` that accepts coordinates
+/// relative to the region top-left corner. The transparency color
+/// is checked before applying the remap table. The destination area is clipped
+/// to the target bounds before iterating, so no time is wasted on invisible pixels.
+pub fn draw_transformed<T, F>(
+    target: &mut T,
+    point: Point,
+    size: Size,
+    transform: Transform,
+    transp: Option<u8>,
+    remap: Option<&Remap>,
+    get: F,
+) where
+    T: PackedTarget + ?Sized,
+    F: Fn(u32, u32) -> u8,
+{
+    let Size { width, height } = size;
+    if width == 0 || height == 0 {
+        return;
+    }
+    let (dst_width, dst_height) = transform.apply_size(width, height);
+    let dst_width = dst_width.min(i32::MAX as u32) as i32;
+    let dst_height = dst_height.min(i32::MAX as u32) as i32;
+    let left = point.x.max(0);
+    let top = point.y.max(0);
+    let right = point.x.saturating_add(dst_width).min(target.width() as i32);
+    let bottom = point
+        .y
+        .saturating_add(dst_height)
+        .min(target.height() as i32);
+    if left >= right || top >= bottom {
+        return;
+    }
+    for dy in top..bottom {
+        let v = (dy - point.y) as u32 / transform.scale;
+        for dx in left..right {
+            let u = (dx - point.x) as u32 / transform.scale;
+            let (sx, sy) = transform.source(u, v, width, height);
+            let luma = get(sx, sy);
+            if Some(luma) == transp {
+                continue;
             }
+            let luma = match remap {
+                Some(remap) => remap[usize::from(luma)],
+                None => luma,
+            };
+            target.set_pixel(Point::new(dx, dy), luma);
+        }
+    }
+}
+
+/// Copy a region of 4 BPP pixels stored in the frame buffer layout into the target.
+///
+/// A fast path for drawing a canvas without any transformations.
+/// Whole bytes are copied if the source and the target pixels have the same
+/// alignment. The clip area and transparency are not checked.
+pub fn copy_packed<T>(target: &mut T, point: Point, src: &[u8], src_width: usize, sub: Rectangle)
+where
+    T: PackedTarget + ?Sized,
+{
+    let Size { width, height } = sub.size;
+    let left = point.x.max(0);
+    let top = point.y.max(0);
+    let right = point
+        .x
+        .saturating_add(width.min(i32::MAX as u32) as i32)
+        .min(target.width() as i32);
+    let bottom = point
+        .y
+        .saturating_add(height.min(i32::MAX as u32) as i32)
+        .min(target.height() as i32);
+    if left >= right || top >= bottom {
+        return;
+    }
+    // The first visible pixel in the source.
+    let sx = sub.top_left.x as usize + (left - point.x) as usize;
+    let sy = sub.top_left.y as usize + (top - point.y) as usize;
+    let left = left as usize;
+    let top = top as usize;
+    let bottom = bottom as usize;
+    let n = right as usize - left;
+    let dst_width = target.width();
+    target.mark_rows_dirty(top..bottom);
+    let dst = target.data_mut();
+    for row in 0..bottom - top {
+        let si = (sy + row) * src_width + sx;
+        let di = (top + row) * dst_width + left;
+        copy_nibbles(dst, di, src, si, n);
+    }
+}
+
+/// Copy `n` pixels between two packed buffers starting at the given pixel indices.
+///
+/// The even pixel is stored in the low nibble.
+fn copy_nibbles(dst: &mut [u8], mut di: usize, src: &[u8], mut si: usize, mut n: usize) {
+    let get = |i: usize| (src[i / PPB] >> (4 * (i % PPB))) & 0b1111;
+    let set = |dst: &mut [u8], i: usize, luma: u8| {
+        let shift = 4 * (i % PPB);
+        dst[i / PPB] = (dst[i / PPB] & !(0b1111 << shift)) | (luma << shift);
+    };
+    if di % PPB != si % PPB {
+        for _ in 0..n {
+            set(dst, di, get(si));
+            di += 1;
+            si += 1;
         }
-        frame.dirty = true;
+        return;
+    }
+    if n > 0 && di % PPB != 0 {
+        set(dst, di, get(si));
+        di += 1;
+        si += 1;
+        n -= 1;
+    }
+    let bytes = n / PPB;
+    dst[di / PPB..di / PPB + bytes].copy_from_slice(&src[si / PPB..si / PPB + bytes]);
+    if n % PPB != 0 {
+        let i = bytes * PPB;
+        set(dst, di + i, get(si + i));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform_source() {
+        // 3x2 region:
+        //   0 1 2
+        //   3 4 5
+        let tr = Transform::from_flags(0b0100, 1); // 90° clockwise
+        assert_eq!(tr.apply_size(3, 2), (2, 3));
+        // Rotated:
+        //   3 0
+        //   4 1
+        //   5 2
+        assert_eq!(tr.source(0, 0, 3, 2), (0, 1));
+        assert_eq!(tr.source(1, 0, 3, 2), (0, 0));
+        assert_eq!(tr.source(0, 2, 3, 2), (2, 1));
+
+        let tr = Transform::from_flags(0b0001, 0); // horizontal flip
+        assert_eq!(tr.scale, 1);
+        assert_eq!(tr.source(0, 0, 3, 2), (2, 0));
+
+        let tr = Transform::from_flags(0b1100, 2); // 270° clockwise
+        assert_eq!(tr.apply_size(3, 2), (4, 6));
+        assert_eq!(tr.source(0, 0, 3, 2), (2, 0));
+        assert_eq!(tr.source(1, 2, 3, 2), (0, 1));
     }
 }
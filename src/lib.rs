@@ -10,26 +10,36 @@
 
 extern crate alloc;
 
+mod audio_log;
 mod battery;
 mod canvas;
 mod color;
 mod config;
 mod error;
+mod font;
 mod frame_buffer;
+mod fuel;
 mod host;
 mod image;
 mod linking;
 mod menu;
 mod net;
+mod pattern;
+mod polygon;
+mod replay;
 mod runtime;
+mod snapshot;
 mod state;
 mod stats;
 mod utils;
 
+#[cfg(test)]
+mod runtime_test;
+
 pub use color::Rgb16;
 pub use config::{FullID, FullIDError, RuntimeConfig};
 pub use error::Error;
 pub use firefly_types::DeviceInfo;
 pub use frame_buffer::{FireflyDisplay, FrameBuffer, HEIGHT, WIDTH};
-pub use runtime::Runtime;
+pub use runtime::{FaultPolicy, Runtime};
 pub use state::NetHandler;
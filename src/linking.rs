@@ -90,6 +90,14 @@ fn select_graphics_external<'a>(
         "set_color" => Func::wrap(ctx, graphics::set_color),
         "set_canvas" => Func::wrap(ctx, graphics::set_canvas),
         "unset_canvas" => Func::wrap(ctx, graphics::unset_canvas),
+        "draw_canvas" => Func::wrap(ctx, graphics::draw_canvas),
+        "set_remap" => Func::wrap(ctx, graphics::set_remap),
+        "unset_remap" => Func::wrap(ctx, graphics::unset_remap),
+        "set_clip" => Func::wrap(ctx, graphics::set_clip),
+        "unset_clip" => Func::wrap(ctx, graphics::unset_clip),
+        "set_offset" => Func::wrap(ctx, graphics::set_offset),
+        "set_fill_pattern" => Func::wrap(ctx, graphics::set_fill_pattern),
+        "unset_fill_pattern" => Func::wrap(ctx, graphics::unset_fill_pattern),
 
         // Primitives (shapes).
         "draw_point" => Func::wrap(ctx, graphics::draw_point),
@@ -101,14 +109,23 @@ fn select_graphics_external<'a>(
         "draw_triangle" => Func::wrap(ctx, graphics::draw_triangle),
         "draw_arc" => Func::wrap(ctx, graphics::draw_arc),
         "draw_sector" => Func::wrap(ctx, graphics::draw_sector),
+        "draw_polyline" => Func::wrap(ctx, graphics::draw_polyline),
+        "draw_polygon" => Func::wrap(ctx, graphics::draw_polygon),
 
         // Binary content from RAM (text, images, etc).
         "draw_qr" => Func::wrap(ctx, graphics::draw_qr),
         "draw_text" => Func::wrap(ctx, graphics::draw_text),
+        "measure_text" => Func::wrap(ctx, graphics::measure_text),
+        "draw_text_box" => Func::wrap(ctx, graphics::draw_text_box),
         "draw_image" => Func::wrap(ctx, graphics::draw_image),
         "draw_nine_slice" => Func::wrap(ctx, graphics::draw_nine_slice),
         "draw_sub_tile" => Func::wrap(ctx, graphics::draw_sub_tile),
+        "draw_tilemap" => Func::wrap(ctx, graphics::draw_tilemap),
         "draw_sub_image" => Func::wrap(ctx, graphics::draw_sub_image),
+        "draw_image_transformed" => Func::wrap(ctx, graphics::draw_image_transformed),
+        "draw_sub_image_transformed" => Func::wrap(ctx, graphics::draw_sub_image_transformed),
+        "get_pixel" => Func::wrap(ctx, graphics::get_pixel),
+        "read_region" => Func::wrap(ctx, graphics::read_region),
         _ => return None,
     };
     Some(func)
@@ -244,6 +261,11 @@ fn select_misc_external<'a>(
         "restart" => Func::wrap(ctx, misc::restart),
         "set_peers" => Func::wrap(ctx, misc::set_peers),
         "quit" => Func::wrap(ctx, misc::quit),
+        "set_fps" => Func::wrap(ctx, misc::set_fps),
+        "get_time" => Func::wrap(ctx, misc::get_time),
+        "get_frame" => Func::wrap(ctx, misc::get_frame),
+        "load_data" => Func::wrap(ctx, misc::load_data),
+        "send_data" => Func::wrap(ctx, misc::send_data),
         _ => return None,
     };
     Some(func)
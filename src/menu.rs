@@ -14,10 +14,16 @@ use firefly_hal::{InputState, Pad};
 
 const LINE_HEIGHT: i32 = 12;
 const OFFSET: i32 = 20;
+/// How many characters of the crash report fit on a single line.
+const REPORT_WIDTH: usize = 32;
+/// How many lines of the crash report fit on the screen.
+const REPORT_LINES: usize = 7;
 
 pub(crate) enum MenuItem {
     Custom(u8, alloc::string::String),
     ScreenShot,
+    SaveState,
+    LoadState,
     Restart,
     Quit,
 }
@@ -27,6 +33,8 @@ impl MenuItem {
         match self {
             Self::Custom(_, t) => t,
             Self::ScreenShot => "take screenshot",
+            Self::SaveState => "save state",
+            Self::LoadState => "load state",
             Self::Restart => "restart app",
             Self::Quit => "exit app",
         }
@@ -71,7 +79,7 @@ pub(crate) struct Menu {
     app_items: alloc::vec::Vec<MenuItem>,
 
     /// System menu items.
-    sys_items: heapless::Vec<MenuItem, 3>,
+    sys_items: heapless::Vec<MenuItem, 5>,
 
     selected: i32,
 
@@ -94,13 +102,26 @@ pub(crate) struct Menu {
     was_released: bool,
 
     dpad: DPad4,
+
+    /// The crash report to show instead of the app menu.
+    ///
+    /// If set, the menu cannot be closed and has only restart and exit items.
+    crash: Option<alloc::string::String>,
 }
 
 impl Menu {
-    pub fn new() -> Self {
-        let mut items = heapless::Vec::<_, 3>::new();
+    /// Create the system menu.
+    ///
+    /// Save states are not supported in multiplayer, so the items
+    /// for them are shown only if `snapshots` is true.
+    pub fn new(snapshots: bool) -> Self {
+        let mut items = heapless::Vec::<_, 5>::new();
         unsafe {
             items.push_unchecked(MenuItem::ScreenShot);
+            if snapshots {
+                items.push_unchecked(MenuItem::SaveState);
+                items.push_unchecked(MenuItem::LoadState);
+            }
             items.push_unchecked(MenuItem::Restart);
             items.push_unchecked(MenuItem::Quit);
         }
@@ -123,6 +144,24 @@ impl Menu {
             .retain(|item| !matches!(item, MenuItem::Custom(i, _) if *i == index));
     }
 
+    /// Show the crash report and let the user only restart or exit the app.
+    pub(crate) fn crash(&mut self, report: &str) {
+        self.app_items.clear();
+        self.sys_items.clear();
+        _ = self.sys_items.push(MenuItem::Restart);
+        _ = self.sys_items.push(MenuItem::Quit);
+        self.crash = Some(wrap_report(report));
+        self.selected = 0;
+        self.active = true;
+        self.rendered = false;
+        self.dirty = true;
+    }
+
+    /// True if the crash report is shown.
+    pub(crate) fn is_crashed(&self) -> bool {
+        self.crash.is_some()
+    }
+
     pub fn handle_input(&mut self, input: &Option<InputState>) -> Option<&MenuItem> {
         let def = InputState::default();
         let input = input.as_ref().unwrap_or(&def);
@@ -135,6 +174,10 @@ impl Menu {
     }
 
     fn handle_menu_button(&mut self, pressed: bool) {
+        // The crash report cannot be closed, the app must be restarted or exited.
+        if self.crash.is_some() {
+            return;
+        }
         // Depending on if menu is open or not, handle the menu button in a way
         // that the button is always released when the app is running.
         if self.active {
@@ -276,6 +319,15 @@ impl Menu {
             text.draw(display)?;
         }
 
+        // Draw the crash report below the menu items.
+        if let Some(report) = &self.crash {
+            let n_items = n_custom + self.sys_items.len() as i32;
+            let point = Point::new(OFFSET + 4, offset_y + n_items * LINE_HEIGHT + 6);
+            let style = MonoTextStyle::new(&FONT_6X9, C::DANGER);
+            let text = Text::new(report, point, style);
+            text.draw(display)?;
+        }
+
         // Draw the separator line.
         if n_custom != 0 {
             let top_left = Point::new(OFFSET, n_custom * LINE_HEIGHT + 4 + OFFSET);
@@ -470,3 +522,36 @@ impl Menu {
         Ok(())
     }
 }
+
+/// Split the crash report into lines that fit into the menu box.
+fn wrap_report(report: &str) -> alloc::string::String {
+    let mut wrapped = alloc::string::String::new();
+    let mut n_lines = 0;
+    for line in report.lines() {
+        let chars: alloc::vec::Vec<char> = line.chars().collect();
+        for chunk in chars.chunks(REPORT_WIDTH) {
+            if n_lines == REPORT_LINES {
+                return wrapped;
+            }
+            if n_lines != 0 {
+                wrapped.push('\n');
+            }
+            wrapped.extend(chunk);
+            n_lines += 1;
+        }
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_report() {
+        let report = "error calling update: out of bounds memory access.\nok";
+        let wrapped = wrap_report(report);
+        let expected = "error calling update: out of bou\nnds memory access.\nok";
+        assert_eq!(wrapped, expected);
+    }
+}
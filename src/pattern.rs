@@ -0,0 +1,133 @@
+use core::convert::Infallible;
+use embedded_graphics::Pixel;
+use embedded_graphics::pixelcolor::Gray4;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+/// Bayer ordered dithering matrix 4x4.
+const BAYER4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Bayer ordered dithering matrix 2x2.
+const BAYER2: [[u8; 2]; 2] = [[0, 2], [3, 1]];
+
+/// A 4x4 two-color pattern used to fill shapes.
+///
+/// The pattern is aligned to the draw target, so that adjacent shapes
+/// filled with the same pattern have no visible seams.
+#[derive(Clone, Copy)]
+pub struct Pattern {
+    /// Bit `y * 4 + x` is set if the pixel at (x, y) has the pattern color.
+    mask: u16,
+    /// The color of the pattern pixels.
+    /// The rest of pixels have the fill color of the shape.
+    color: Gray4,
+}
+
+impl Pattern {
+    pub const fn new(mask: u16, color: Gray4) -> Self {
+        Self { mask, color }
+    }
+
+    /// The bit mask of the pixels that have the pattern color.
+    pub fn mask(&self) -> u16 {
+        self.mask
+    }
+
+    pub fn color(&self) -> Gray4 {
+        self.color
+    }
+
+    /// Every second pixel has the pattern color.
+    pub fn checkerboard(color: Gray4) -> Self {
+        Self::from_fn(color, |x, y| (x + y) % 2 == 1)
+    }
+
+    /// Ordered dithering with `level` out of 4 pixels having the pattern color.
+    pub fn bayer2(level: u8, color: Gray4) -> Self {
+        Self::from_fn(color, |x, y| BAYER2[y % 2][x % 2] < level)
+    }
+
+    /// Ordered dithering with `level` out of 16 pixels having the pattern color.
+    pub fn bayer4(level: u8, color: Gray4) -> Self {
+        Self::from_fn(color, |x, y| BAYER4[y][x] < level)
+    }
+
+    fn from_fn<F: Fn(usize, usize) -> bool>(color: Gray4, f: F) -> Self {
+        let mut mask = 0;
+        for y in 0..4 {
+            for x in 0..4 {
+                if f(x, y) {
+                    mask |= 1 << (y * 4 + x);
+                }
+            }
+        }
+        Self { mask, color }
+    }
+
+    /// The color of the pixel at the given point of a shape with the given fill color.
+    pub fn color_at(&self, point: Point, fill: Gray4) -> Gray4 {
+        let x = (point.x & 0b11) as u16;
+        let y = (point.y & 0b11) as u16;
+        if self.mask & (1 << (y * 4 + x)) != 0 {
+            self.color
+        } else {
+            fill
+        }
+    }
+
+    /// Colors of 4 pixels of the given row packed into 2 bytes, the even pixel first.
+    pub fn packed_row(&self, y: i32, fill: Gray4) -> [u8; 2] {
+        let luma = |x| self.color_at(Point::new(x, y), fill).luma();
+        [luma(0) | (luma(1) << 4), luma(2) | (luma(3) << 4)]
+    }
+}
+
+/// A draw target that can be filled with a pattern.
+pub trait PatternTarget: DrawTarget<Color = Gray4, Error = Infallible> {
+    /// Fill the area with the pattern.
+    fn fill_pattern(&mut self, area: &Rectangle, pattern: &Pattern, fill: Gray4) {
+        let pixels = area
+            .points()
+            .map(|point| Pixel(point, pattern.color_at(point, fill)));
+        _ = self.draw_iter(pixels);
+    }
+}
+
+/// A draw target adapter that replaces the color of drawn pixels with the pattern.
+pub struct Patterned<'t, T> {
+    target: &'t mut T,
+    pattern: Pattern,
+}
+
+impl<'t, T: PatternTarget> Patterned<'t, T> {
+    pub fn new(target: &'t mut T, pattern: Pattern) -> Self {
+        Self { target, pattern }
+    }
+}
+
+impl<T: PatternTarget> OriginDimensions for Patterned<'_, T> {
+    fn size(&self) -> Size {
+        self.target.bounding_box().size
+    }
+}
+
+impl<T: PatternTarget> DrawTarget for Patterned<'_, T> {
+    type Color = Gray4;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let pattern = self.pattern;
+        let pixels = pixels
+            .into_iter()
+            .map(|Pixel(point, color)| Pixel(point, pattern.color_at(point, color)));
+        self.target.draw_iter(pixels)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.target.fill_pattern(area, &self.pattern, color);
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use alloc::vec::Vec;
+use embedded_graphics::pixelcolor::Gray4;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Polyline, PrimitiveStyle, Rectangle, StyledDrawable};
+
+/// A closed shape with an arbitrary number of vertices.
+///
+/// The fill uses the even-odd rule. A pixel is filled if its top-left corner
+/// is inside of the polygon, so the right and bottom edges are not filled
+/// and adjacent polygons don't overlap.
+pub struct Polygon {
+    /// The vertices with the first one repeated at the end to close the outline.
+    points: Vec<Point>,
+}
+
+impl Polygon {
+    pub fn new(mut points: Vec<Point>) -> Self {
+        if let Some(first) = points.first() {
+            points.push(*first);
+        }
+        Self { points }
+    }
+
+    fn fill<D>(&self, color: Gray4, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Gray4>,
+    {
+        let Some(top) = self.points.iter().map(|p| p.y).min() else {
+            return Ok(());
+        };
+        let Some(bottom) = self.points.iter().map(|p| p.y).max() else {
+            return Ok(());
+        };
+        // Skip the rows that are out of the draw target.
+        let bbox = target.bounding_box();
+        let top = top.max(bbox.top_left.y);
+        let bottom = bottom.min(bbox.top_left.y.saturating_add(bbox.size.height as i32));
+
+        let mut xs = Vec::new();
+        for y in top..bottom {
+            xs.clear();
+            for edge in self.points.windows(2) {
+                let (a, b) = (edge[0], edge[1]);
+                if (a.y <= y && y < b.y) || (b.y <= y && y < a.y) {
+                    xs.push(intersect(a, b, y));
+                }
+            }
+            xs.sort_unstable();
+            for span in xs.chunks_exact(2) {
+                let width = span[1].saturating_sub(span[0]);
+                if width > 0 {
+                    let area = Rectangle::new(Point::new(span[0], y), Size::new(width as u32, 1));
+                    target.fill_solid(&area, color)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StyledDrawable<PrimitiveStyle<Gray4>> for Polygon {
+    type Color = Gray4;
+    type Output = ();
+
+    fn draw_styled<D>(&self, style: &PrimitiveStyle<Gray4>, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Gray4>,
+    {
+        if let Some(color) = style.fill_color {
+            self.fill(color, target)?;
+        }
+        if let Some(color) = style.stroke_color {
+            let style = PrimitiveStyle::with_stroke(color, style.stroke_width);
+            Polyline::new(&self.points).draw_styled(&style, target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Find X of the edge between the two points at the given row.
+///
+/// The result is rounded up, so that pixels at or to the right of it
+/// are on the inner side of the edge.
+fn intersect(a: Point, b: Point, y: i32) -> i32 {
+    let num = (i64::from(y) - i64::from(a.y)) * (i64::from(b.x) - i64::from(a.x));
+    let den = i64::from(b.y) - i64::from(a.y);
+    let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+    let mut dx = num.div_euclid(den);
+    if num.rem_euclid(den) != 0 {
+        dx += 1;
+    }
+    (i64::from(a.x) + dx) as i32
+}
@@ -0,0 +1,242 @@
+use crate::config::FullID;
+use crate::net::Action;
+use crate::utils::read_all;
+use alloc::vec::Vec;
+use embedded_io::Write;
+use firefly_hal::*;
+
+/// The first byte of the replay file.
+///
+/// Changed every time the file layout changes, so that old recordings
+/// are rejected instead of being replayed wrongly. The recordings without
+/// the recorded time used 0x52.
+const MAGIC: u8 = 0x53;
+
+/// The name of the replay file in the app data dir.
+const FILE_NAME: &str = "replay";
+
+/// The size of the file header in bytes.
+///
+/// * 0: magic number
+/// * 1-4: the initial random seed, u32 little-endian
+const HEADER_SIZE: usize = 5;
+
+/// The size of a single frame record in bytes.
+///
+/// * 0: flags (bit 0: input is present, bit 1: pad is touched)
+/// * 1-2: pad X, i16 little-endian
+/// * 3-4: pad Y, i16 little-endian
+/// * 5: buttons
+/// * 6-9: random seed, u32 little-endian
+/// * 10: system action
+/// * 11-18: time since the app start in nanoseconds, u64 little-endian
+const FRAME_SIZE: usize = 19;
+
+/// How often (in frames) the recorded frames are flushed on disk.
+const FLUSH_EVERY: usize = 60;
+
+/// The recording or playback of the app input.
+pub(crate) enum Replay {
+    Record(Recorder),
+    Play(Player),
+}
+
+/// The state of a single update frame that affects the app execution.
+pub(crate) struct ReplayFrame {
+    pub input: Option<InputState>,
+    pub seed: u32,
+    pub action: Action,
+    /// The monotonic clock exposed to the app.
+    ///
+    /// Recorded so that apps with frame-rate independent physics
+    /// see exactly the same time deltas when replayed.
+    pub uptime: u64,
+}
+
+/// Writes the per-frame input into the replay file in the app data dir.
+pub(crate) struct Recorder {
+    /// Encoded frames that aren't written on disk yet.
+    buf: Vec<u8>,
+}
+
+impl Recorder {
+    /// Create an empty replay file and start recording.
+    ///
+    /// The seed is the state of the random generator before the app is started.
+    pub fn new(device: &mut DeviceImpl, id: &FullID, seed: u32) -> Result<Self, FSError> {
+        let dir_path = &["data", id.author(), id.app()];
+        let mut dir = device.open_dir(dir_path)?;
+        let mut stream = dir.create_file(FILE_NAME)?;
+        let seed = seed.to_le_bytes();
+        stream.write_all(&[MAGIC, seed[0], seed[1], seed[2], seed[3]])?;
+        Ok(Self {
+            buf: Vec::with_capacity(FRAME_SIZE * FLUSH_EVERY),
+        })
+    }
+
+    /// Record the state of the current frame.
+    pub fn push(&mut self, frame: &ReplayFrame) {
+        let mut raw = [0u8; FRAME_SIZE];
+        if let Some(input) = frame.input.clone() {
+            raw[0] = 0b01;
+            if let Some(pad) = input.pad {
+                let (x, y): (i16, i16) = pad.into();
+                raw[0] |= 0b10;
+                raw[1..3].copy_from_slice(&x.to_le_bytes());
+                raw[3..5].copy_from_slice(&y.to_le_bytes());
+            }
+            raw[5] = input.buttons;
+        }
+        raw[6..10].copy_from_slice(&frame.seed.to_le_bytes());
+        raw[10] = match frame.action {
+            Action::None => 0,
+            Action::Restart => 1,
+            Action::Exit => 2,
+        };
+        raw[11..19].copy_from_slice(&frame.uptime.to_le_bytes());
+        self.buf.extend_from_slice(&raw);
+    }
+
+    /// True if enough frames are accumulated to write them on disk.
+    pub fn should_flush(&self) -> bool {
+        self.buf.len() >= FRAME_SIZE * FLUSH_EVERY
+    }
+
+    /// Append all the recorded frames to the replay file.
+    pub fn flush(&mut self, device: &mut DeviceImpl, id: &FullID) -> Result<(), FSError> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let dir_path = &["data", id.author(), id.app()];
+        let mut dir = device.open_dir(dir_path)?;
+        let mut stream = dir.append_file(FILE_NAME)?;
+        stream.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+/// Feeds the recorded per-frame input back into the app.
+pub(crate) struct Player {
+    /// The state of the random generator before the app is started.
+    pub seed: u32,
+    /// Encoded frames, without the header.
+    raw: Vec<u8>,
+    /// The offset of the next frame to play.
+    offset: usize,
+}
+
+impl Player {
+    /// Load the replay file from the app data dir.
+    ///
+    /// Returns [`None`] if the file is not a valid replay.
+    pub fn new(device: &mut DeviceImpl, id: &FullID) -> Result<Option<Self>, FSError> {
+        let dir_path = &["data", id.author(), id.app()];
+        let mut dir = device.open_dir(dir_path)?;
+        let stream = dir.open_file(FILE_NAME)?;
+        let raw = read_all(stream)?;
+        if raw.len() < HEADER_SIZE || raw[0] != MAGIC {
+            return Ok(None);
+        }
+        if !(raw.len() - HEADER_SIZE).is_multiple_of(FRAME_SIZE) {
+            return Ok(None);
+        }
+        let seed = u32::from_le_bytes([raw[1], raw[2], raw[3], raw[4]]);
+        Ok(Some(Self {
+            seed,
+            raw,
+            offset: HEADER_SIZE,
+        }))
+    }
+
+    /// Get the state of the next recorded frame.
+    ///
+    /// Returns [`None`] when all recorded frames are played.
+    pub fn next_frame(&mut self) -> Option<ReplayFrame> {
+        let raw = self.raw.get(self.offset..self.offset + FRAME_SIZE)?;
+        self.offset += FRAME_SIZE;
+        let input = if raw[0] & 0b01 == 0 {
+            None
+        } else {
+            let pad = if raw[0] & 0b10 == 0 {
+                None
+            } else {
+                let x = i16::from_le_bytes([raw[1], raw[2]]);
+                let y = i16::from_le_bytes([raw[3], raw[4]]);
+                Some((x, y).into())
+            };
+            Some(InputState {
+                pad,
+                buttons: raw[5],
+            })
+        };
+        let seed = u32::from_le_bytes([raw[6], raw[7], raw[8], raw[9]]);
+        let action = match raw[10] {
+            1 => Action::Restart,
+            2 => Action::Exit,
+            _ => Action::None,
+        };
+        let mut uptime = [0u8; 8];
+        uptime.copy_from_slice(&raw[11..19]);
+        Some(ReplayFrame {
+            input,
+            seed,
+            action,
+            uptime: u64::from_le_bytes(uptime),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut rec = Recorder { buf: Vec::new() };
+        let frames = [
+            ReplayFrame {
+                input: None,
+                seed: 13,
+                action: Action::None,
+                uptime: 16_666_666,
+            },
+            ReplayFrame {
+                input: Some(InputState {
+                    pad: Some((-12_i16, 400_i16).into()),
+                    buttons: 0b101,
+                }),
+                seed: 0xdead_beef,
+                action: Action::Restart,
+                uptime: u64::MAX,
+            },
+        ];
+        for frame in &frames {
+            rec.push(frame);
+        }
+        assert_eq!(rec.buf.len(), FRAME_SIZE * 2);
+        assert!(!rec.should_flush());
+
+        let mut player = Player {
+            seed: 0,
+            raw: rec.buf,
+            offset: 0,
+        };
+        let frame = player.next_frame().unwrap();
+        assert!(frame.input.is_none());
+        assert_eq!(frame.seed, 13);
+        assert!(frame.action == Action::None);
+        assert_eq!(frame.uptime, 16_666_666);
+
+        let frame = player.next_frame().unwrap();
+        let input = frame.input.unwrap();
+        let (x, y): (i16, i16) = input.pad.unwrap().into();
+        assert_eq!((x, y), (-12, 400));
+        assert_eq!(input.buttons, 0b101);
+        assert_eq!(frame.seed, 0xdead_beef);
+        assert!(frame.action == Action::Restart);
+        assert_eq!(frame.uptime, u64::MAX);
+
+        assert!(player.next_frame().is_none());
+    }
+}
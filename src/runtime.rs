@@ -1,9 +1,11 @@
 use crate::color::FromRGB;
 use crate::config::{FullID, RuntimeConfig};
 use crate::error::Error;
-use crate::frame_buffer::FireflyDisplay;
+use crate::frame_buffer::{FireflyDisplay, FrameBuffer};
+use crate::fuel::FuelLimits;
 use crate::linking::populate_externals;
-use crate::state::{NetHandler, State};
+use crate::snapshot::{self, SnapshotAction, SnapshotError};
+use crate::state::{DEFAULT_FPS, NetHandler, State};
 use crate::stats::StatsTracker;
 use crate::utils::read_all;
 use alloc::boxed::Box;
@@ -15,16 +17,17 @@ use embedded_io::Read;
 use firefly_hal::*;
 use firefly_types::*;
 
-/// Default frames per second.
-const FPS: u8 = 60;
 const KB: u32 = 1024;
 
-const FUEL_INIT: u64 = 10_000_000;
-const FUEL_BOOT: u64 = 10_000_000;
-const FUEL_UPDATE: u64 = 10_000_000;
-const FUEL_RENDER: u64 = 10_000_000;
-const FUEL_BEFORE_EXIT: u64 = 10_000_000;
-const FUEL_CHEAT: u64 = 10_000_000;
+/// What to do when the app fails (traps) in a callback.
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub enum FaultPolicy {
+    /// Stop the runtime and return the error.
+    #[default]
+    Abort,
+    /// Show the crash report on the screen and let the user restart or exit the app.
+    Report,
+}
 
 pub struct Runtime<'a, D, C>
 where
@@ -34,13 +37,21 @@ where
     display: D,
     instance: wasmi::Instance,
     store: wasmi::Store<Box<State<'a>>>,
+    /// The hash of the app binary, used to validate save states.
+    bin_hash: u32,
 
     update: Option<wasmi::TypedFunc<(), ()>>,
     render: Option<wasmi::TypedFunc<(), ()>>,
     before_exit: Option<wasmi::TypedFunc<(), ()>>,
     cheat: Option<wasmi::TypedFunc<(i32, i32), (i32,)>>,
     handle_menu: Option<wasmi::TypedFunc<(u32,), ()>>,
+    handle_data: Option<wasmi::TypedFunc<(u32,), ()>>,
+
+    /// How much fuel each callback can consume.
+    fuel: FuelLimits,
 
+    /// The frame rate requested by the app.
+    fps: u8,
     /// Time to render a single frame to match the expected FPS.
     per_frame: Duration,
     /// The last time when the frame was updated.
@@ -51,6 +62,12 @@ where
     lagging_frames: u8,
     fast_frames: u8,
     render_every: u8,
+    /// True if the runtime is driven by [`Runtime::step`] instead of the real time.
+    headless: bool,
+
+    fault_policy: FaultPolicy,
+    /// True if the app has failed and the crash report is shown.
+    crashed: bool,
 
     stats: Option<StatsTracker>,
 }
@@ -96,6 +113,12 @@ where
         }
         let sudo = meta.sudo;
         let launcher = meta.launcher;
+        // The launcher must stay responsive, so it always has the default budgets.
+        let fuel = if launcher {
+            FuelLimits::default()
+        } else {
+            FuelLimits::load(&mut rom_dir)?
+        };
 
         let res = config.device.serial_start();
         if let Err(err) = res {
@@ -152,8 +175,9 @@ where
             wasm_bin
         };
 
+        let bin_hash = snapshot::hash_bin(&wasm_bin);
         let mut store = wasmi::Store::new(&engine, state);
-        _ = store.set_fuel(FUEL_BOOT);
+        _ = store.set_fuel(fuel.boot);
         let instance = {
             let module = wasmi::Module::new(&engine, wasm_bin)?;
             let mut externals = Vec::new();
@@ -165,17 +189,24 @@ where
             display: config.display,
             instance,
             store,
+            bin_hash,
             update: None,
             render: None,
             before_exit: None,
             cheat: None,
             handle_menu: None,
+            handle_data: None,
+            fuel,
             stats: None,
-            per_frame: Duration::from_fps(u32::from(FPS)),
+            fps: DEFAULT_FPS,
+            per_frame: Duration::from_fps(u32::from(DEFAULT_FPS)),
             n_frames: 0,
             lagging_frames: 0,
             fast_frames: 0,
             render_every: 2,
+            headless: false,
+            fault_policy: FaultPolicy::default(),
+            crashed: false,
             prev_time: now,
             prev_lag: Duration::from_ms(0),
         };
@@ -184,11 +215,80 @@ where
 
     /// Set how often `render` should be called relative to `update`.
     ///
-    /// Exposed excludively for firefly-test to combat FPS auto-adjustment.
+    /// In the headless mode, the value stays as set. Otherwise, it is
+    /// auto-adjusted based on how fast the app is.
     pub fn set_render_every(&mut self, render_every: u8) {
         self.render_every = render_every;
     }
 
+    /// Record the input of every frame into the `replay` file in the app data dir.
+    ///
+    /// Must be called before [`Runtime::start`] so that the whole session is recorded.
+    pub fn record(&mut self) -> Result<(), Error> {
+        let state = self.store.data_mut();
+        state.start_recording()
+    }
+
+    /// Use the input recorded by [`Runtime::record`] instead of the device input.
+    ///
+    /// Must be called before [`Runtime::start`]. When all recorded frames are played,
+    /// the runtime switches back to reading the device input.
+    pub fn replay(&mut self) -> Result<(), Error> {
+        let state = self.store.data_mut();
+        state.start_replay()
+    }
+
+    /// Set what to do when the app fails in `update`, `render`, `handle_menu`,
+    /// or in a callback triggered by a serial request (`cheat` and `handle_data`).
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    /// Run a single update without waiting for the next frame.
+    ///
+    /// The first call switches the runtime into the headless mode
+    /// intended for testing apps on the host:
+    ///
+    /// * The input is not read from the device but set by [`Runtime::set_input`].
+    /// * Each step is exactly one frame of the virtual time, there is no delay.
+    /// * FPS is not auto-adjusted, `render` is called as set by [`Runtime::set_render_every`].
+    ///
+    /// Returns true if the app requested to exit.
+    pub fn step(&mut self) -> Result<bool, Error> {
+        if !self.headless {
+            self.headless = true;
+            let state = self.store.data_mut();
+            state.headless = true;
+        }
+        self.update()
+    }
+
+    /// Set the input that the app will see on the next [`Runtime::step`].
+    ///
+    /// The input stays the same for all the following steps until changed.
+    pub fn set_input(&mut self, input: Option<InputState>) {
+        let state = self.store.data_mut();
+        state.headless_input = input;
+    }
+
+    /// The frame buffer with the image rendered by the app.
+    pub fn frame(&self) -> &FrameBuffer {
+        let state = self.store.data();
+        &state.frame
+    }
+
+    /// The current app stash, including changes not yet persisted.
+    pub fn stash(&self) -> &[u8] {
+        let state = self.store.data();
+        &state.stash
+    }
+
+    /// The app stats, if loaded.
+    pub fn app_stats(&self) -> Option<&Stats> {
+        let state = self.store.data();
+        state.app_stats.as_ref()
+    }
+
     pub fn display_mut(&mut self) -> &mut D {
         &mut self.display
     }
@@ -208,12 +308,12 @@ where
         let ins = self.instance;
         // The `_initialize` and `_start` functions are defined by wasip1.
         let f = ins.get_typed_func::<(), ()>(&self.store, "_initialize");
-        self.call_callback("_initialize", f.ok(), FUEL_INIT)?;
+        self.call_callback("_initialize", f.ok(), self.fuel.init)?;
         let f = ins.get_typed_func::<(), ()>(&self.store, "_start");
-        self.call_callback("_start", f.ok(), FUEL_INIT)?;
+        self.call_callback("_start", f.ok(), self.fuel.init)?;
         // The `boot` function is defined by our spec.
         let f = ins.get_typed_func::<(), ()>(&self.store, "boot");
-        self.call_callback("boot", f.ok(), FUEL_BOOT)?;
+        self.call_callback("boot", f.ok(), self.fuel.boot)?;
 
         // Other functions defined by our spec.
         self.update = ins.get_typed_func(&self.store, "update").ok();
@@ -221,6 +321,7 @@ where
         self.before_exit = ins.get_typed_func(&self.store, "before_exit").ok();
         self.cheat = ins.get_typed_func(&self.store, "cheat").ok();
         self.handle_menu = ins.get_typed_func(&self.store, "handle_menu").ok();
+        self.handle_data = ins.get_typed_func(&self.store, "handle_data").ok();
         Ok(())
     }
 
@@ -233,10 +334,13 @@ where
         let state = self.store.data_mut();
         let menu_was_active = state.menu.active();
         let menu_index = state.update();
+        self.sync_fps();
+        let state = self.store.data_mut();
 
         let menu_is_active = state.menu.active();
         if menu_is_active {
-            if self.n_frames.is_multiple_of(120)
+            // Check the battery every 2 seconds.
+            if self.n_frames.is_multiple_of(self.fps * 2)
                 && let Some(battery) = &mut state.battery
             {
                 let res = battery.update(&mut state.device);
@@ -244,7 +348,7 @@ where
                     state.device.log_error("battery", err);
                 }
             }
-            self.n_frames = (self.n_frames + 1) % (FPS * 4);
+            self.n_frames = (self.n_frames + 1) % (self.fps * 4);
             // We render the system menu directly on the screen,
             // bypassing the frame buffer. That way, we preserve
             // the frame buffer rendered by the app.
@@ -256,7 +360,7 @@ where
             self.delay();
             return Ok(false);
         } else if menu_was_active {
-            state.frame.dirty = true;
+            state.frame.mark_all_dirty();
             if self.render.is_none() {
                 // When menu was open but now closed, if the app doesn't have the `render`
                 // callback defined, the screen flushing will never be called.
@@ -269,17 +373,37 @@ where
             }
         }
 
+        // The crash report was closed by restarting or exiting the app.
+        if self.crashed {
+            let state = self.store.data();
+            return Ok(state.exit);
+        }
+
+        // If save or load state is selected in the menu, apply it.
+        let state = self.store.data_mut();
+        if let Some(action) = state.snapshot.take() {
+            self.apply_snapshot(action);
+        }
+
         // If a custom menu item is selected, trigger the handle_menu callback.
         if let Some(custom_menu) = menu_index
             && let Some(handle_menu) = self.handle_menu
-            && let Err(err) = handle_menu.call(&mut self.store, (custom_menu as u32,))
         {
-            let stats = self.store.data().runtime_stats();
-            return Err(Error::FuncCall("handle_menu", err, stats));
+            let fuel = self.fuel.handle_menu;
+            _ = self.store.set_fuel(fuel);
+            if let Err(err) = handle_menu.call(&mut self.store, (custom_menu as u32,)) {
+                let stats = self.store.data().runtime_stats();
+                let err = Error::from_call("handle_menu", err, fuel, stats);
+                return self.fault(err);
+            }
         };
 
-        // TODO: continue execution even if an update fails.
-        let fuel_update = self.call_callback("update", self.update, FUEL_UPDATE)?;
+        let state = self.store.data_mut();
+        state.n_updates = state.n_updates.wrapping_add(1);
+        let fuel_update = match self.call_callback("update", self.update, self.fuel.update) {
+            Ok(fuel_update) => fuel_update,
+            Err(err) => return self.fault(err),
+        };
         if let Some(stats) = &mut self.stats {
             stats.update_fuel.add(fuel_update);
         }
@@ -294,10 +418,12 @@ where
         // Check if the app is lagging.
         // Adjust, if needed, how often "render" is called.
         // If we have time to spare, delay rendering to keep steady frame rate.
-        if self.fast_frames >= FPS {
+        if self.headless {
+            // The render frequency is set explicitly by the test harness.
+        } else if self.fast_frames >= self.fps {
             self.render_every = (self.render_every - 1).max(1);
             self.fast_frames = 0;
-        } else if self.lagging_frames >= FPS {
+        } else if self.lagging_frames >= self.fps {
             self.render_every = (self.render_every + 1).min(8);
             self.lagging_frames = 0;
         }
@@ -308,14 +434,17 @@ where
         // The frame number must be updated after calculating "should_render"
         // so that "render" is always called on the first "update" run
         // (when the app is just launched).
-        self.n_frames = (self.n_frames + 1) % (FPS * 4);
+        self.n_frames = (self.n_frames + 1) % (self.fps * 4);
         if should_render {
-            let fuel_render = self.call_callback("render", self.render, FUEL_RENDER)?;
+            let fuel_render = match self.call_callback("render", self.render, self.fuel.render) {
+                Ok(fuel_render) => fuel_render,
+                Err(err) => return self.fault(err),
+            };
             if let Some(stats) = &mut self.stats {
                 stats.render_fuel.add(fuel_render);
             }
             let state = self.store.data();
-            if state.frame.dirty {
+            if state.frame.is_dirty() {
                 self.flush_frame()?;
             }
         }
@@ -323,8 +452,63 @@ where
         Ok(state.exit)
     }
 
+    /// Handle the app failure according to the fault policy.
+    ///
+    /// With [`FaultPolicy::Report`], the error is logged and the crash report
+    /// is shown in the system menu which lets the user restart or exit the app.
+    fn fault(&mut self, err: Error) -> Result<bool, Error> {
+        if self.fault_policy == FaultPolicy::Abort {
+            return Err(err);
+        }
+        let state = self.store.data_mut();
+        state.device.log_error("runtime", &err);
+        _ = state.save_log("crash", &err);
+        let report = alloc::format!("{err}");
+        state.menu.crash(&report);
+        self.crashed = true;
+        Ok(false)
+    }
+
+    /// Save the app state into the snapshot file or restore it from there.
+    fn apply_snapshot(&mut self, action: SnapshotAction) {
+        let state = self.store.data_mut();
+        let res = if !matches!(state.net_handler.get_mut(), NetHandler::None) {
+            Err(SnapshotError::InNet)
+        } else {
+            match action {
+                SnapshotAction::Save => {
+                    snapshot::save(&mut self.store, self.instance, self.bin_hash)
+                }
+                SnapshotAction::Load => {
+                    snapshot::load(&mut self.store, self.instance, self.bin_hash)
+                }
+            }
+        };
+        if let Err(err) = res {
+            let state = self.store.data_mut();
+            state.device.log_error("snapshot", err);
+        }
+    }
+
+    /// Apply the frame rate if it was changed by the app.
+    fn sync_fps(&mut self) {
+        let state = self.store.data();
+        if state.fps == self.fps {
+            return;
+        }
+        self.fps = state.fps;
+        self.per_frame = Duration::from_fps(u32::from(self.fps));
+        self.n_frames = 0;
+        self.fast_frames = 0;
+        self.lagging_frames = 0;
+        self.prev_lag = Duration::from_ms(0);
+    }
+
     // Delay the screen flushing to adjust the frame rate.
     fn delay(&mut self) {
+        if self.headless {
+            return;
+        }
         let state = self.store.data();
         let now = state.device.now();
         let elapsed = now - self.prev_time;
@@ -339,7 +523,7 @@ where
                 }
                 state.device.delay(delay);
             }
-            self.fast_frames = (self.fast_frames + 1) % (FPS * 4);
+            self.fast_frames = (self.fast_frames + 1) % (self.fps * 4);
             self.prev_lag = Duration::from_ms(0);
             self.lagging_frames = 0;
         } else {
@@ -347,7 +531,7 @@ where
                 stats.lags += elapsed - self.per_frame;
             }
             self.prev_lag = elapsed - self.per_frame;
-            self.lagging_frames = (self.lagging_frames + 1) % (FPS * 4);
+            self.lagging_frames = (self.lagging_frames + 1) % (self.fps * 4);
             self.fast_frames = 0;
         }
         self.prev_time = state.device.now();
@@ -360,8 +544,13 @@ where
     /// 3. Releases [`Device`] ownership.
     /// 3. Tells which app to run next.
     pub fn finalize(mut self) -> Result<RuntimeConfig<'a, D, C>, Error> {
-        self.call_callback("before_exit", self.before_exit, FUEL_BEFORE_EXIT)?;
+        // The crashed app instance cannot be trusted to run any more code.
+        // And if it fails again, the choice made in the crash report would be lost.
+        if !self.crashed {
+            self.call_callback("before_exit", self.before_exit, self.fuel.before_exit)?;
+        }
         let mut state = self.store.into_data();
+        state.save_replay();
         state.save_stash();
         state.update_app_stats();
         state.save_app_stats();
@@ -438,17 +627,22 @@ where
         Ok(())
     }
 
-    fn handle_serial_request(&mut self, req: serial::Request) -> Result<(), Error> {
+    pub(crate) fn handle_serial_request(&mut self, req: serial::Request) -> Result<(), Error> {
         match req {
             serial::Request::Cheat(a, b) => {
                 let Some(cheat) = self.cheat else {
                     return Err(Error::CheatUndefined);
                 };
+                if self.crashed {
+                    let resp = serial::Response::Log("ERROR(runtime): the app has crashed".into());
+                    self.serial_send(resp)?;
+                    return Ok(());
+                }
                 let state = self.store.data_mut();
                 if !matches!(state.net_handler.get_mut(), NetHandler::None) {
                     return Err(Error::CheatInNet);
                 }
-                _ = self.store.set_fuel(FUEL_CHEAT);
+                _ = self.store.set_fuel(self.fuel.cheat);
                 match cheat.call(&mut self.store, (a, b)) {
                     Ok((result,)) => {
                         let resp = serial::Response::Cheat(result);
@@ -456,7 +650,8 @@ where
                     }
                     Err(err) => {
                         let stats = self.store.data().runtime_stats();
-                        return Err(Error::FuncCall("cheat", err, stats));
+                        let err = Error::from_call("cheat", err, self.fuel.cheat, stats);
+                        self.serial_fault(err)?;
                     }
                 }
             }
@@ -500,22 +695,55 @@ where
                 let resp = serial::Response::Ok;
                 self.serial_send(resp)?;
             }
-            serial::Request::Buttons(_) => todo!(),
-            serial::Request::Data(_) => todo!(),
+            serial::Request::Buttons(buttons) => {
+                let state = self.store.data_mut();
+                state.remote_buttons = buttons;
+                let resp = serial::Response::Ok;
+                self.serial_send(resp)?;
+            }
+            serial::Request::Data(data) => {
+                let Some(handle_data) = self.handle_data else {
+                    let msg = "ERROR(runtime): the app doesn't have handle_data callback";
+                    let resp = serial::Response::Log(msg.into());
+                    self.serial_send(resp)?;
+                    return Ok(());
+                };
+                if self.crashed {
+                    let resp = serial::Response::Log("ERROR(runtime): the app has crashed".into());
+                    self.serial_send(resp)?;
+                    return Ok(());
+                }
+                let state = self.store.data_mut();
+                let len = data.len() as u32;
+                state.serial_data = data;
+                let fuel = self.fuel.handle_data;
+                _ = self.store.set_fuel(fuel);
+                if let Err(err) = handle_data.call(&mut self.store, (len,)) {
+                    let stats = self.store.data().runtime_stats();
+                    let err = Error::from_call("handle_data", err, fuel, stats);
+                    return self.serial_fault(err);
+                }
+                let resp = serial::Response::Ok;
+                self.serial_send(resp)?;
+            }
         }
         Ok(())
     }
 
+    /// Handle the app failure in a callback triggered by a serial request.
+    ///
+    /// If the fault policy allows the runtime to keep running,
+    /// the error is also reported back to the serial port.
+    fn serial_fault(&mut self, err: Error) -> Result<(), Error> {
+        let msg = alloc::format!("ERROR(runtime): {err}");
+        self.fault(err)?;
+        let resp = serial::Response::Log(msg.as_str().into());
+        self.serial_send(resp)
+    }
+
     fn serial_send(&mut self, resp: serial::Response) -> Result<(), Error> {
-        let encoded = match resp.encode_vec() {
-            Ok(encoded) => encoded,
-            Err(err) => return Err(Error::SerialEncode(err)),
-        };
-        let res = self.device_mut().serial_send(&encoded);
-        if let Err(err) = res {
-            return Err(Error::SerialSend(err));
-        }
-        Ok(())
+        let state = self.store.data_mut();
+        state.serial_send(resp)
     }
 
     /// Call a guest function. Returns the amount of fuel consumed.
@@ -530,7 +758,7 @@ where
             && let Err(err) = f.call(&mut self.store, ())
         {
             let stats = self.store.data().runtime_stats();
-            return Err(Error::FuncCall(name, err, stats));
+            return Err(Error::from_call(name, err, fuel, stats));
         }
         let Ok(left) = self.store.get_fuel() else {
             return Ok(0);
@@ -0,0 +1,565 @@
+use crate::config::{FullID, RuntimeConfig};
+use crate::error::Error;
+use crate::frame_buffer::{FireflyDisplay, FrameBuffer, HEIGHT, WIDTH};
+use crate::runtime::{FaultPolicy, Runtime};
+use crate::state::NetHandler;
+use core::convert::Infallible;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use firefly_hal::{DeviceConfig, DeviceImpl};
+use firefly_types::{Encode, Meta, Stats, serial};
+use std::path::PathBuf;
+
+/// wasm opcode: trap.
+const UNREACHABLE: u8 = 0x00;
+/// wasm opcode: end of the function body.
+const END: u8 = 0x0b;
+/// wasm opcode: call the function with the given index.
+const CALL: u8 = 0x10;
+/// wasm opcode: push i32 constant.
+const I32_CONST: u8 = 0x41;
+/// wasm opcode: push i64 constant.
+const I64_CONST: u8 = 0x42;
+/// wasm opcode: add two i32 numbers.
+const I32_ADD: u8 = 0x6a;
+/// wasm opcode: unsigned division of two i64 numbers.
+const I64_DIV_U: u8 = 0x80;
+/// wasm opcode: convert i64 into i32.
+const I32_WRAP_I64: u8 = 0xa7;
+
+#[test]
+fn test_fault_abort() {
+    let wasm = Module::default()
+        .func("update", Sig::Void, &[UNREACHABLE, END])
+        .encode();
+    let mut runtime = make_runtime("fault-abort", &wasm);
+    start(&mut runtime);
+    let res = runtime.step();
+    assert!(matches!(res, Err(Error::FuncCall("update", ..))));
+}
+
+#[test]
+fn test_fault_report() {
+    let wasm = Module::default()
+        .func("update", Sig::Void, &[UNREACHABLE, END])
+        .encode();
+    let mut runtime = make_runtime("fault-report", &wasm);
+    runtime.set_fault_policy(FaultPolicy::Report);
+    start(&mut runtime);
+    // The crash report is shown instead of returning the error
+    // and the failed app is not called again.
+    assert!(!step(&mut runtime));
+    assert!(!step(&mut runtime));
+}
+
+/// The injected input is seen by the app and the rendered frame can be read back.
+#[test]
+fn test_step_input() {
+    let wasm = Module::default()
+        .import("input", "read_buttons", Sig::I32I32)
+        .import("graphics", "clear_screen", Sig::I32)
+        .func(
+            "render",
+            Sig::Void,
+            // clear_screen(read_buttons(0) + 1)
+            &[I32_CONST, 0, CALL, 0, I32_CONST, 1, I32_ADD, CALL, 1, END],
+        )
+        .encode();
+    let mut runtime = make_runtime("step-input", &wasm);
+    runtime.set_render_every(1);
+    start(&mut runtime);
+
+    runtime.set_input(Some(firefly_hal::InputState {
+        pad: None,
+        buttons: 0b10,
+    }));
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(0, 0), Some(2));
+    // The input stays the same until changed.
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(WIDTH - 1, HEIGHT - 1), Some(2));
+
+    runtime.set_input(None);
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(0, 0), Some(0));
+    assert!(runtime.stash().is_empty());
+}
+
+/// Buttons sent over the serial port are merged into the local input until released.
+#[test]
+fn test_serial_buttons() {
+    let wasm = Module::default()
+        .import("input", "read_buttons", Sig::I32I32)
+        .import("graphics", "clear_screen", Sig::I32)
+        .func(
+            "render",
+            Sig::Void,
+            // clear_screen(read_buttons(0) + 1)
+            &[I32_CONST, 0, CALL, 0, I32_CONST, 1, I32_ADD, CALL, 1, END],
+        )
+        .encode();
+    let mut runtime = make_runtime("serial-buttons", &wasm);
+    runtime.set_render_every(1);
+    start(&mut runtime);
+
+    send(&mut runtime, serial::Request::Buttons(0b100));
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(0, 0), Some(0b100));
+
+    runtime.set_input(Some(firefly_hal::InputState {
+        pad: None,
+        buttons: 0b10,
+    }));
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(0, 0), Some(0b110));
+
+    // Releasing the remote buttons doesn't affect the local ones.
+    send(&mut runtime, serial::Request::Buttons(0));
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(0, 0), Some(0b10));
+    runtime.set_input(None);
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(0, 0), Some(0));
+}
+
+/// The recorded input and time are replayed instead of the device input.
+#[test]
+fn test_record_replay() {
+    // clear_screen(read_buttons(0) + 1)
+    // draw_point(0, 0, get_time() / frame_time + 1)
+    let mut render = vec![I32_CONST, 0, CALL, 1, I32_CONST, 1, I32_ADD, CALL, 2];
+    render.extend_from_slice(&[I32_CONST, 0, I32_CONST, 0, CALL, 0, I64_CONST]);
+    write_i32(&mut render, 1_000_000_000 / 60);
+    render.extend_from_slice(&[I64_DIV_U, I32_WRAP_I64, I32_CONST, 1, I32_ADD, CALL, 3, END]);
+    let wasm = Module::default()
+        .import("misc", "get_time", Sig::I64)
+        .import("input", "read_buttons", Sig::I32I32)
+        .import("graphics", "clear_screen", Sig::I32)
+        .import("graphics", "draw_point", Sig::I32x3)
+        .func("render", Sig::Void, &render)
+        .encode();
+    let inputs = [0b1, 0b10, 0b0, 0b101];
+
+    let mut runtime = make_runtime("record-replay", &wasm);
+    if let Err(err) = runtime.record() {
+        panic!("cannot start recording: {err}");
+    }
+    runtime.set_render_every(1);
+    start(&mut runtime);
+    let mut recorded = Vec::new();
+    for buttons in inputs {
+        runtime.set_input(Some(firefly_hal::InputState { pad: None, buttons }));
+        assert!(!step(&mut runtime));
+        let frame = runtime.frame();
+        recorded.push((frame.get_pixel(0, 0), frame.get_pixel(1, 0)));
+    }
+    let mut config = match runtime.finalize() {
+        Ok(config) => config,
+        Err(err) => panic!("cannot finalize: {err}"),
+    };
+
+    // Run the same app again on the same device.
+    config.id = Some(test_id());
+    let mut runtime = match Runtime::new(config) {
+        Ok(runtime) => runtime,
+        Err(err) => panic!("cannot create runtime: {err}"),
+    };
+    if let Err(err) = runtime.replay() {
+        panic!("cannot start replay: {err}");
+    }
+    runtime.set_render_every(1);
+    start(&mut runtime);
+    // The local input is ignored while the recording is played.
+    runtime.set_input(Some(firefly_hal::InputState {
+        pad: None,
+        buttons: 0b1000,
+    }));
+    for (i, buttons) in inputs.iter().enumerate() {
+        assert!(!step(&mut runtime));
+        let frame = runtime.frame();
+        let replayed = (frame.get_pixel(0, 0), frame.get_pixel(1, 0));
+        assert_eq!(replayed, recorded[i]);
+        assert_eq!(replayed, (Some(i as u8 + 1), Some(*buttons)));
+    }
+    // When all frames are played, the local input is used again.
+    assert!(!step(&mut runtime));
+    assert_eq!(runtime.frame().get_pixel(1, 0), Some(0b1000));
+}
+
+/// In the headless mode, each step takes exactly one frame of the virtual time.
+#[test]
+fn test_step_virtual_time() {
+    let mut render = vec![CALL, 0, I64_CONST];
+    write_i32(&mut render, 1_000_000_000 / 60);
+    // clear_screen(get_time() / frame_time + 1)
+    render.extend_from_slice(&[I64_DIV_U, I32_WRAP_I64, I32_CONST, 1, I32_ADD, CALL, 1, END]);
+    let wasm = Module::default()
+        .import("misc", "get_time", Sig::I64)
+        .import("graphics", "clear_screen", Sig::I32)
+        .func("render", Sig::Void, &render)
+        .encode();
+    let mut runtime = make_runtime("step-time", &wasm);
+    runtime.set_render_every(1);
+    start(&mut runtime);
+
+    for frame in 1..=4 {
+        assert!(!step(&mut runtime));
+        assert_eq!(runtime.frame().get_pixel(0, 0), Some(frame));
+    }
+}
+
+/// When the app has crashed, `before_exit` is not called
+/// and the choice made in the crash report is respected.
+#[test]
+fn test_finalize_after_crash() {
+    let wasm = Module::default()
+        .func("update", Sig::Void, &[UNREACHABLE, END])
+        .func("before_exit", Sig::Void, &[UNREACHABLE, END])
+        .encode();
+    let mut runtime = make_runtime("finalize-crash", &wasm);
+    runtime.set_fault_policy(FaultPolicy::Report);
+    start(&mut runtime);
+    assert!(!step(&mut runtime));
+
+    // Press and release the select button to pick "restart app".
+    runtime.set_input(Some(firefly_hal::InputState {
+        pad: None,
+        buttons: 0b1,
+    }));
+    assert!(!step(&mut runtime));
+    runtime.set_input(None);
+    assert!(step(&mut runtime));
+
+    let config = match runtime.finalize() {
+        Ok(config) => config,
+        Err(err) => panic!("cannot finalize: {err}"),
+    };
+    assert!(config.id == Some(test_id()));
+}
+
+/// The crash report in the launcher can be closed even though
+/// the launcher has no app menu.
+#[test]
+fn test_launcher_crash() {
+    let wasm = Module::default()
+        .func("update", Sig::Void, &[UNREACHABLE, END])
+        .encode();
+    let mut runtime = make_launcher("launcher-crash", &wasm);
+    runtime.set_fault_policy(FaultPolicy::Report);
+    start(&mut runtime);
+    assert!(!step(&mut runtime));
+
+    // Press and release the select button to pick "restart app".
+    runtime.set_input(Some(firefly_hal::InputState {
+        pad: None,
+        buttons: 0b1,
+    }));
+    assert!(!step(&mut runtime));
+    runtime.set_input(None);
+    assert!(step(&mut runtime));
+
+    let config = match runtime.finalize() {
+        Ok(config) => config,
+        Err(err) => panic!("cannot finalize: {err}"),
+    };
+    assert!(config.id == Some(test_id()));
+}
+
+fn start(runtime: &mut TestRuntime) {
+    if let Err(err) = runtime.start() {
+        panic!("cannot start: {err}");
+    }
+}
+
+fn send(runtime: &mut TestRuntime, req: serial::Request) {
+    if let Err(err) = runtime.handle_serial_request(req) {
+        panic!("cannot handle serial request: {err}");
+    }
+}
+
+fn step(runtime: &mut TestRuntime) -> bool {
+    match runtime.step() {
+        Ok(exit) => exit,
+        Err(err) => panic!("cannot step: {err}"),
+    }
+}
+
+pub(crate) type TestRuntime = Runtime<'static, TestDisplay, Rgb888>;
+
+/// A display that doesn't show anything.
+pub(crate) struct TestDisplay;
+
+impl OriginDimensions for TestDisplay {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for TestDisplay {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, _pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        Ok(())
+    }
+}
+
+impl FireflyDisplay for TestDisplay {
+    type Error = Infallible;
+
+    fn render_fb(&mut self, frame: &mut FrameBuffer) -> Result<(), Self::Error> {
+        frame.draw(self)
+    }
+
+    fn rotate(&mut self, _rotate: bool) {}
+
+    fn set_brightness(&mut self, _brightness: u8) {}
+}
+
+/// Function signatures used by the test modules.
+#[derive(Clone, Copy)]
+pub(crate) enum Sig {
+    /// `() -> ()`
+    Void,
+    /// `(i32) -> ()`
+    I32,
+    /// `(i32) -> i32`
+    I32I32,
+    /// `() -> i64`
+    I64,
+    /// `(i32, i32, i32) -> ()`
+    I32x3,
+}
+
+/// Types for all [`Sig`] variants, in the same order.
+const TYPES: &[&[u8]] = &[
+    &[0x60, 0, 0],
+    &[0x60, 1, 0x7f, 0],
+    &[0x60, 1, 0x7f, 1, 0x7f],
+    &[0x60, 0, 1, 0x7e],
+    &[0x60, 3, 0x7f, 0x7f, 0x7f, 0],
+];
+
+/// A minimal wasm module encoder, to avoid bundling binary test apps.
+///
+/// Function indices are assigned in order: imports first, then functions.
+#[derive(Default)]
+pub(crate) struct Module {
+    imports: Vec<(&'static str, &'static str, Sig)>,
+    funcs: Vec<(&'static str, Sig, Vec<u8>)>,
+    /// The number of pages of the exported memory. Zero means no memory.
+    pages: u32,
+    /// Exported mutable i32 globals with their initial values.
+    globals: Vec<(&'static str, i32)>,
+}
+
+impl Module {
+    pub fn import(mut self, module: &'static str, name: &'static str, sig: Sig) -> Self {
+        self.imports.push((module, name, sig));
+        self
+    }
+
+    /// Add an exported function. The body must end with [`END`].
+    pub fn func(mut self, name: &'static str, sig: Sig, body: &[u8]) -> Self {
+        self.funcs.push((name, sig, body.to_vec()));
+        self
+    }
+
+    pub fn memory(mut self, pages: u32) -> Self {
+        self.pages = pages;
+        self
+    }
+
+    pub fn global(mut self, name: &'static str, value: i32) -> Self {
+        self.globals.push((name, value));
+        self
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+        let mut types = Vec::new();
+        write_u32(&mut types, TYPES.len() as u32);
+        for t in TYPES {
+            types.extend_from_slice(t);
+        }
+        write_section(&mut out, 1, &types);
+
+        if !self.imports.is_empty() {
+            let mut imports = Vec::new();
+            write_u32(&mut imports, self.imports.len() as u32);
+            for (module, name, sig) in &self.imports {
+                write_name(&mut imports, module);
+                write_name(&mut imports, name);
+                imports.push(0x00);
+                imports.push(*sig as u8);
+            }
+            write_section(&mut out, 2, &imports);
+        }
+
+        let mut funcs = Vec::new();
+        write_u32(&mut funcs, self.funcs.len() as u32);
+        for (_, sig, _) in &self.funcs {
+            funcs.push(*sig as u8);
+        }
+        write_section(&mut out, 3, &funcs);
+
+        if self.pages != 0 {
+            let mut memory = vec![1, 0x00];
+            write_u32(&mut memory, self.pages);
+            write_section(&mut out, 5, &memory);
+        }
+
+        if !self.globals.is_empty() {
+            let mut globals = Vec::new();
+            write_u32(&mut globals, self.globals.len() as u32);
+            for (_, value) in &self.globals {
+                globals.extend_from_slice(&[0x7f, 0x01, I32_CONST]);
+                write_i32(&mut globals, *value);
+                globals.push(END);
+            }
+            write_section(&mut out, 6, &globals);
+        }
+
+        let mut exports = Vec::new();
+        let n_exports = self.funcs.len() + self.globals.len() + usize::from(self.pages != 0);
+        write_u32(&mut exports, n_exports as u32);
+        for (i, (name, _, _)) in self.funcs.iter().enumerate() {
+            write_name(&mut exports, name);
+            exports.push(0x00);
+            write_u32(&mut exports, (self.imports.len() + i) as u32);
+        }
+        for (i, (name, _)) in self.globals.iter().enumerate() {
+            write_name(&mut exports, name);
+            exports.push(0x03);
+            write_u32(&mut exports, i as u32);
+        }
+        if self.pages != 0 {
+            write_name(&mut exports, "memory");
+            exports.extend_from_slice(&[0x02, 0x00]);
+        }
+        write_section(&mut out, 7, &exports);
+
+        let mut code = Vec::new();
+        write_u32(&mut code, self.funcs.len() as u32);
+        for (_, _, body) in &self.funcs {
+            // No locals.
+            write_u32(&mut code, body.len() as u32 + 1);
+            code.push(0x00);
+            code.extend_from_slice(body);
+        }
+        write_section(&mut out, 10, &code);
+        out
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+    out.push(id);
+    write_u32(out, content.len() as u32);
+    out.extend_from_slice(content);
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Write unsigned LEB128.
+pub(crate) fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Write signed LEB128.
+pub(crate) fn write_i32(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub(crate) fn test_id() -> FullID {
+    FullID::from_str("test-author", "test-app").unwrap()
+}
+
+/// Create a runtime for the given wasm binary in a fresh virtual FS.
+///
+/// Each test must use a unique name because tests run in parallel.
+pub(crate) fn make_runtime(name: &str, wasm: &[u8]) -> TestRuntime {
+    runtime_at(get_vfs(name, wasm, false))
+}
+
+/// Like [`make_runtime`] but the app is marked as the launcher.
+fn make_launcher(name: &str, wasm: &[u8]) -> TestRuntime {
+    runtime_at(get_vfs(name, wasm, true))
+}
+
+fn runtime_at(root: PathBuf) -> TestRuntime {
+    let config = DeviceConfig {
+        root,
+        ..Default::default()
+    };
+    let config = RuntimeConfig {
+        id: Some(test_id()),
+        device: DeviceImpl::new(config),
+        display: TestDisplay,
+        net_handler: NetHandler::None,
+    };
+    match Runtime::new(config) {
+        Ok(runtime) => runtime,
+        Err(err) => panic!("cannot create runtime: {err}"),
+    }
+}
+
+fn get_vfs(name: &str, wasm: &[u8], launcher: bool) -> PathBuf {
+    let root = std::env::temp_dir().join("firefly-runtime-test").join(name);
+    _ = std::fs::remove_dir_all(&root);
+    let rom_dir = root.join("roms").join("test-author").join("test-app");
+    let data_dir = root.join("data").join("test-author").join("test-app");
+    std::fs::create_dir_all(&rom_dir).unwrap();
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::create_dir_all(root.join("sys")).unwrap();
+
+    let meta = Meta {
+        app_id: "test-app",
+        app_name: "Test App",
+        author_id: "test-author",
+        author_name: "Test Author",
+        launcher,
+        sudo: false,
+        version: 1,
+    };
+    let meta = meta.encode_vec().unwrap();
+    std::fs::write(rom_dir.join("_meta"), meta).unwrap();
+    std::fs::write(rom_dir.join("_bin"), wasm).unwrap();
+    let stats = Stats {
+        minutes: [0; 4],
+        longest_play: [0; 4],
+        launches: [0; 4],
+        installed_on: (2026, 1, 1),
+        updated_on: (2026, 1, 1),
+        launched_on: (0, 0, 0),
+        xp: 0,
+        badges: Box::new([]),
+        scores: Box::new([]),
+    };
+    let stats = stats.encode_vec().unwrap();
+    std::fs::write(data_dir.join("stats"), stats).unwrap();
+    root
+}
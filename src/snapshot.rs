@@ -0,0 +1,755 @@
+//! Save states: snapshots of the whole guest instance.
+//!
+//! A snapshot includes the linear memory, exported globals, the frame buffer
+//! (pixels and palette), the draw state (canvas, remap, clip, offset,
+//! and fill pattern), the frame counter and uptime, the audio graph,
+//! and the random seed.
+//!
+//! Limitations:
+//!
+//! * The audio graph is rebuilt from the log of calls that made it
+//!   (see [`crate::audio_log`]), so all sounds start playing from the beginning.
+//! * Non-exported globals are not included because they cannot be accessed
+//!   from the host. Snapshots are taken between callbacks, so globals that are
+//!   restored by the end of each callback (like `__stack_pointer` of LLVM-based
+//!   toolchains) are fine. But apps that keep state (like the heap pointer
+//!   of a garbage collector) in non-exported mutable globals will be broken
+//!   after loading a snapshot.
+//!
+//! File layout (all numbers little-endian):
+//!
+//! * magic number (u8)
+//! * author ID and app ID, each is the length (u8) followed by the ID
+//! * hash of the app binary (u32)
+//! * random seed (u32)
+//! * palette, 16 colors, 2 bytes each
+//! * frame buffer pixels
+//! * canvas: flag (u8), followed, if the flag is 1, by the address, size,
+//!   and width (u32 each)
+//! * remap: flag (u8), followed, if the flag is 1, by 16 colors (u8 each)
+//! * clip: flag (u8), followed, if the flag is 1, by x and y (i32 each)
+//!   and width and height (u32 each)
+//! * offset, x and y (i32 each)
+//! * fill pattern: flag (u8), followed, if the flag is 1, by the mask (u16)
+//!   and the color (u8)
+//! * number of updates (u32) and uptime in nanoseconds (u64)
+//! * number of audio calls (u32) followed by the calls
+//! * number of globals (u32), each is the name length (u8), the name,
+//!   the type (u8), and the raw value (u64)
+//! * memory size (u32) followed by the memory data
+use crate::audio_log::AudioLog;
+use crate::canvas::Canvas;
+use crate::color::Rgb16;
+use crate::image::Remap;
+use crate::pattern::Pattern;
+use crate::state::State;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use embedded_graphics::pixelcolor::Gray4;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_io::{Read, ReadExactError, Write};
+use firefly_hal::*;
+use wasmi::Val;
+
+/// The first byte of the snapshot file.
+const MAGIC: u8 = 0x53;
+
+/// The name of the snapshot file in the app data dir.
+const FILE_NAME: &str = "snapshot";
+
+/// The size of a wasm memory page.
+const PAGE_SIZE: usize = 64 * 1024;
+
+type Store<'a> = wasmi::Store<Box<State<'a>>>;
+
+/// A save state action selected in the system menu.
+#[derive(Copy, Clone)]
+pub(crate) enum SnapshotAction {
+    Save,
+    Load,
+}
+
+pub(crate) enum SnapshotError {
+    FS(FSError),
+    /// The snapshot file is corrupted.
+    Invalid,
+    /// The snapshot is made for a different app or a different version of the app.
+    Mismatch,
+    /// Cannot grow the guest memory to fit the snapshot.
+    Memory,
+    /// Cannot rebuild the audio graph, most likely because an audio file is missing.
+    Audio,
+    /// Save states are not supported in multiplayer.
+    InNet,
+}
+
+impl From<FSError> for SnapshotError {
+    fn from(value: FSError) -> Self {
+        Self::FS(value)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FS(err) => write!(f, "file system error: {err}"),
+            Self::Invalid => write!(f, "the snapshot file is invalid"),
+            Self::Mismatch => write!(f, "the snapshot is made for another app version"),
+            Self::Memory => write!(f, "cannot grow memory to fit the snapshot"),
+            Self::Audio => write!(f, "cannot rebuild the audio graph"),
+            Self::InNet => write!(f, "save states are disabled in multiplayer"),
+        }
+    }
+}
+
+/// Write the snapshot of the running app into the app data dir.
+///
+/// The hash is the hash of the app binary, see [`hash_bin`].
+pub(crate) fn save(
+    store: &mut Store<'_>,
+    instance: wasmi::Instance,
+    hash: u32,
+) -> Result<(), SnapshotError> {
+    let globals = collect_globals(store, instance);
+    let memory = store.data().memory;
+    let (data, state): (&[u8], _) = match memory {
+        Some(memory) => {
+            let (data, state) = memory.data_and_store_mut(&mut *store);
+            (&*data, state)
+        }
+        None => (&[], store.data_mut()),
+    };
+
+    let dir_path = &["data", state.id.author(), state.id.app()];
+    let mut dir = state.device.open_dir(dir_path)?;
+    let mut stream = dir.create_file(FILE_NAME)?;
+    let mut header = Vec::new();
+    header.push(MAGIC);
+    for id in [state.id.author(), state.id.app()] {
+        header.push(id.len() as u8);
+        header.extend_from_slice(id.as_bytes());
+    }
+    header.extend_from_slice(&hash.to_le_bytes());
+    header.extend_from_slice(&state.seed.to_le_bytes());
+    for color in &state.frame.palette {
+        header.push(color.0);
+        header.push(color.1);
+    }
+    stream.write_all(&header).map_err(FSError::from)?;
+    stream
+        .write_all(&state.frame.data[..])
+        .map_err(FSError::from)?;
+    let mut raw_state = Vec::new();
+    encode_draw_state(state, &mut raw_state);
+    state.audio_log.encode(&mut raw_state);
+    stream.write_all(&raw_state).map_err(FSError::from)?;
+
+    let mut raw_globals = Vec::new();
+    raw_globals.extend_from_slice(&(globals.len() as u32).to_le_bytes());
+    for (name, kind, value) in &globals {
+        raw_globals.push(name.len() as u8);
+        raw_globals.extend_from_slice(name.as_bytes());
+        raw_globals.push(*kind);
+        raw_globals.extend_from_slice(&value.to_le_bytes());
+    }
+    stream.write_all(&raw_globals).map_err(FSError::from)?;
+
+    let size = data.len() as u32;
+    stream
+        .write_all(&size.to_le_bytes())
+        .map_err(FSError::from)?;
+    stream.write_all(data).map_err(FSError::from)?;
+    stream.flush().map_err(FSError::from)?;
+    Ok(())
+}
+
+/// Restore the running app from the snapshot in the app data dir.
+///
+/// The whole file is read and validated before anything is changed,
+/// so an invalid snapshot leaves the running app intact.
+pub(crate) fn load(
+    store: &mut Store<'_>,
+    instance: wasmi::Instance,
+    hash: u32,
+) -> Result<(), SnapshotError> {
+    let raw = {
+        let state = store.data_mut();
+        let dir_path = &["data", state.id.author(), state.id.app()];
+        let mut dir = state.device.open_dir(dir_path)?;
+        let size = dir.get_file_size(FILE_NAME)? as usize;
+        let mut stream = dir.open_file(FILE_NAME)?;
+        let mut raw = state.device.alloc_psram(size);
+        raw.resize(size, 0);
+        read_exact(&mut stream, &mut raw)?;
+        raw
+    };
+    let snapshot = parse(&raw, store, instance, hash)?;
+
+    // Apply the snapshot. Rebuilding the audio graph and growing memory
+    // are the only steps that can fail, so they go first.
+    let Some(audio) = snapshot.audio.replay(store.data_mut()) else {
+        return Err(SnapshotError::Audio);
+    };
+    if let Some(memory) = store.data().memory {
+        let current = memory.data(&*store).len();
+        let size = snapshot.memory.len();
+        if size > current {
+            let pages = (size - current).div_ceil(PAGE_SIZE) as u32;
+            if memory.grow(&mut *store, pages.into()).is_err() {
+                return Err(SnapshotError::Memory);
+            }
+        }
+        let data = memory.data_mut(&mut *store);
+        data[..size].copy_from_slice(snapshot.memory);
+        data[size..].fill(0);
+    }
+    for (global, val) in snapshot.globals {
+        // The type and mutability are already checked when parsing.
+        _ = global.set(&mut *store, val);
+    }
+    let state = store.data_mut();
+    state.seed = snapshot.seed;
+    state.frame.palette = snapshot.palette;
+    state.frame.data[..].copy_from_slice(snapshot.frame);
+    state.frame.mark_all_dirty();
+    let draw = snapshot.draw;
+    state.canvas = draw.canvas;
+    state.remap = draw.remap;
+    state.clip = draw.clip;
+    state.frame.set_clip(draw.clip);
+    state.offset = draw.offset;
+    state.fill_pattern = draw.fill_pattern;
+    state.n_updates = draw.n_updates;
+    state.uptime = draw.uptime;
+    state.audio = audio;
+    state.audio_log = snapshot.audio;
+    Ok(())
+}
+
+/// The draw state and frame counters of [`State`] stored in the snapshot.
+struct DrawState {
+    canvas: Option<Canvas>,
+    remap: Option<Remap>,
+    clip: Option<Rectangle>,
+    offset: Point,
+    fill_pattern: Option<Pattern>,
+    n_updates: u32,
+    uptime: u64,
+}
+
+fn encode_draw_state(state: &State, buf: &mut Vec<u8>) {
+    match &state.canvas {
+        Some(canvas) => {
+            buf.push(1);
+            let (start, size, width) = canvas.raw();
+            for n in [start, size, width] {
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        None => buf.push(0),
+    }
+    match &state.remap {
+        Some(remap) => {
+            buf.push(1);
+            buf.extend_from_slice(remap);
+        }
+        None => buf.push(0),
+    }
+    match &state.clip {
+        Some(clip) => {
+            buf.push(1);
+            buf.extend_from_slice(&clip.top_left.x.to_le_bytes());
+            buf.extend_from_slice(&clip.top_left.y.to_le_bytes());
+            buf.extend_from_slice(&clip.size.width.to_le_bytes());
+            buf.extend_from_slice(&clip.size.height.to_le_bytes());
+        }
+        None => buf.push(0),
+    }
+    buf.extend_from_slice(&state.offset.x.to_le_bytes());
+    buf.extend_from_slice(&state.offset.y.to_le_bytes());
+    match &state.fill_pattern {
+        Some(pattern) => {
+            buf.push(1);
+            buf.extend_from_slice(&pattern.mask().to_le_bytes());
+            buf.push(pattern.color().luma());
+        }
+        None => buf.push(0),
+    }
+    buf.extend_from_slice(&state.n_updates.to_le_bytes());
+    buf.extend_from_slice(&state.uptime.to_le_bytes());
+}
+
+/// Parse the draw state. The canvas is checked against the memory later.
+fn parse_draw_state(reader: &mut Reader<'_>) -> Result<DrawState, SnapshotError> {
+    let canvas = if reader.flag()? {
+        let start = reader.u32()?;
+        let size = reader.u32()?;
+        let width = reader.u32()?;
+        if start.checked_add(size).is_none() {
+            return Err(SnapshotError::Invalid);
+        }
+        Some(Canvas::new(start, size, width))
+    } else {
+        None
+    };
+    let remap = if reader.flag()? {
+        let mut remap: Remap = [0; 16];
+        remap.copy_from_slice(reader.take(16)?);
+        if remap.iter().any(|c| *c > 15) {
+            return Err(SnapshotError::Invalid);
+        }
+        Some(remap)
+    } else {
+        None
+    };
+    let clip = if reader.flag()? {
+        let point = Point::new(reader.i32()?, reader.i32()?);
+        let size = Size::new(reader.u32()?, reader.u32()?);
+        Some(Rectangle::new(point, size))
+    } else {
+        None
+    };
+    let offset = Point::new(reader.i32()?, reader.i32()?);
+    let fill_pattern = if reader.flag()? {
+        let mask = reader.u16()?;
+        let luma = reader.u8()?;
+        if luma > 15 {
+            return Err(SnapshotError::Invalid);
+        }
+        Some(Pattern::new(mask, Gray4::new(luma)))
+    } else {
+        None
+    };
+    Ok(DrawState {
+        canvas,
+        remap,
+        clip,
+        offset,
+        fill_pattern,
+        n_updates: reader.u32()?,
+        uptime: reader.u64()?,
+    })
+}
+
+/// A snapshot parsed and validated but not yet applied.
+struct Snapshot<'a> {
+    seed: u32,
+    palette: [Rgb16; 16],
+    frame: &'a [u8],
+    draw: DrawState,
+    audio: AudioLog,
+    globals: Vec<(wasmi::Global, Val)>,
+    memory: &'a [u8],
+}
+
+/// Parse the snapshot file and check that it can be applied to the running app.
+fn parse<'a>(
+    raw: &'a [u8],
+    store: &Store<'_>,
+    instance: wasmi::Instance,
+    hash: u32,
+) -> Result<Snapshot<'a>, SnapshotError> {
+    let mut reader = Reader { raw };
+    if reader.u8()? != MAGIC {
+        return Err(SnapshotError::Invalid);
+    }
+    let state = store.data();
+    for id in [state.id.author(), state.id.app()] {
+        let len = reader.u8()?;
+        if reader.take(usize::from(len))? != id.as_bytes() {
+            return Err(SnapshotError::Mismatch);
+        }
+    }
+    if reader.u32()? != hash {
+        return Err(SnapshotError::Mismatch);
+    }
+    let seed = reader.u32()?;
+    let mut palette = [Rgb16(0, 0); 16];
+    for color in &mut palette {
+        let raw = reader.take(2)?;
+        *color = Rgb16(raw[0], raw[1]);
+    }
+    let frame = reader.take(state.frame.data.len())?;
+    let draw = parse_draw_state(&mut reader)?;
+    let audio = AudioLog::decode(&mut reader)?;
+
+    let n_globals = reader.u32()?;
+    let mut globals = Vec::new();
+    for _ in 0..n_globals {
+        let name_len = reader.u8()?;
+        let name = reader.take(usize::from(name_len))?;
+        let kind = reader.u8()?;
+        let mut bits = [0u8; 8];
+        bits.copy_from_slice(reader.take(8)?);
+        let Ok(name) = core::str::from_utf8(name) else {
+            return Err(SnapshotError::Invalid);
+        };
+        let Some(val) = decode_val(kind, u64::from_le_bytes(bits)) else {
+            return Err(SnapshotError::Invalid);
+        };
+        let Some(global) = instance.get_global(store, name) else {
+            return Err(SnapshotError::Mismatch);
+        };
+        let ty = global.ty(store);
+        if ty.content() != val.ty() {
+            return Err(SnapshotError::Mismatch);
+        }
+        // Immutable globals cannot change, so there is nothing to restore.
+        if ty.mutability().is_mut() {
+            globals.push((global, val));
+        }
+    }
+
+    let size = reader.u32()? as usize;
+    let memory = reader.take(size)?;
+    if !reader.raw.is_empty() {
+        return Err(SnapshotError::Invalid);
+    }
+    if store.data().memory.is_none() && size != 0 {
+        return Err(SnapshotError::Mismatch);
+    }
+    if let Some(canvas) = &draw.canvas {
+        let (start, canvas_size, _) = canvas.raw();
+        // The sum cannot overflow, it's checked when parsing.
+        if store.data().memory.is_none() || (start + canvas_size) as usize > size {
+            return Err(SnapshotError::Invalid);
+        }
+    }
+    Ok(Snapshot {
+        seed,
+        palette,
+        frame,
+        draw,
+        audio,
+        globals,
+        memory,
+    })
+}
+
+/// Sequential reader of the snapshot file content.
+pub(crate) struct Reader<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, size: usize) -> Result<&'a [u8], SnapshotError> {
+        if size > self.raw.len() {
+            return Err(SnapshotError::Invalid);
+        }
+        let (head, tail) = self.raw.split_at(size);
+        self.raw = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A boolean stored as u8, either 0 or 1.
+    pub fn flag(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Invalid),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SnapshotError> {
+        let mut bytes = [0u8; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, SnapshotError> {
+        Ok(self.u32()? as i32)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, SnapshotError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+}
+
+/// Hash of the app binary, used to detect snapshots made for another app version.
+///
+/// It's 32-bit FNV-1a: fast, simple, and good enough to detect changes.
+pub(crate) fn hash_bin(bin: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in bin {
+        hash ^= u32::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// Get name, type, and raw value of all globals exported by the instance.
+fn collect_globals(store: &Store<'_>, instance: wasmi::Instance) -> Vec<(String, u8, u64)> {
+    let mut globals = Vec::new();
+    for export in instance.exports(store) {
+        let name = String::from(export.name());
+        let Some(global) = export.into_global() else {
+            continue;
+        };
+        if let Some((kind, value)) = encode_val(&global.get(store)) {
+            globals.push((name, kind, value));
+        }
+    }
+    globals
+}
+
+fn encode_val(val: &Val) -> Option<(u8, u64)> {
+    let encoded = match val {
+        Val::I32(v) => (0, u64::from(*v as u32)),
+        Val::I64(v) => (1, *v as u64),
+        Val::F32(v) => (2, u64::from(v.to_bits())),
+        Val::F64(v) => (3, v.to_bits()),
+        _ => return None,
+    };
+    Some(encoded)
+}
+
+fn decode_val(kind: u8, value: u64) -> Option<Val> {
+    let val = match kind {
+        0 => Val::I32(value as u32 as i32),
+        1 => Val::I64(value as i64),
+        2 => Val::F32(wasmi::F32::from_bits(value as u32)),
+        3 => Val::F64(wasmi::F64::from_bits(value)),
+        _ => return None,
+    };
+    Some(val)
+}
+
+fn read_exact<R: Read>(stream: &mut R, buf: &mut [u8]) -> Result<(), SnapshotError>
+where
+    FSError: From<R::Error>,
+{
+    match stream.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(ReadExactError::UnexpectedEof) => Err(SnapshotError::Invalid),
+        Err(ReadExactError::Other(err)) => Err(SnapshotError::FS(err.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_log::{AudioCall, NodeKind};
+    use crate::config::FullID;
+    use crate::image::PackedTarget;
+    use crate::runtime_test::Module;
+    use crate::state::NetHandler;
+    use firefly_hal::{DeviceConfig, DeviceImpl};
+    use std::path::PathBuf;
+
+    const HASH: u32 = 0x1234_5678;
+
+    #[test]
+    fn test_val_roundtrip() {
+        let vals = [
+            Val::I32(-13),
+            Val::I64(i64::MIN),
+            Val::F32(wasmi::F32::from(1.5f32)),
+            Val::F64(wasmi::F64::from(-2.25f64)),
+        ];
+        for val in vals {
+            let (kind, value) = encode_val(&val).unwrap();
+            let decoded = decode_val(kind, value).unwrap();
+            assert_eq!(encode_val(&decoded), Some((kind, value)));
+        }
+        assert!(decode_val(7, 0).is_none());
+    }
+
+    #[test]
+    fn test_hash_bin() {
+        assert_eq!(hash_bin(b""), 0x811c_9dc5);
+        assert_eq!(hash_bin(b"a"), 0xe40c_292c);
+        assert_ne!(hash_bin(b"ab"), hash_bin(b"ba"));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let (root, mut store, instance) = make_store("save-load");
+        set_app_state(&mut store, instance, 13);
+        assert!(save(&mut store, instance, HASH).is_ok());
+
+        set_app_state(&mut store, instance, 0);
+        store.data_mut().frame.mark_clean();
+        assert!(load(&mut store, instance, HASH).is_ok());
+        check_app_state(&store, instance, 13);
+        assert!(store.data().frame.is_dirty());
+
+        // Snapshots of another app version cannot be loaded.
+        set_app_state(&mut store, instance, 0);
+        let res = load(&mut store, instance, HASH + 1);
+        assert!(matches!(res, Err(SnapshotError::Mismatch)));
+        check_app_state(&store, instance, 0);
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// The draw state and the audio graph are replaced by the ones from the snapshot.
+    #[test]
+    fn test_load_draw_state() {
+        let (root, mut store, instance) = make_store("load-draw-state");
+        let state = store.data_mut();
+        state.canvas = Some(Canvas::new(2000, 800, 40));
+        state.remap = Some([3; 16]);
+        state.clip = Some(Rectangle::new(Point::new(-4, 5), Size::new(30, 20)));
+        state.frame.set_clip(state.clip);
+        state.offset = Point::new(7, -8);
+        state.fill_pattern = Some(Pattern::checkerboard(Gray4::new(9)));
+        state.n_updates = 90;
+        state.uptime = 1_500_000_000;
+        let node = NodeKind::Gain { lvl: 0.5 };
+        let proc = node.build(state).unwrap();
+        let id = state.audio.add_node(0, proc).ok().unwrap();
+        state.audio_log.push(AudioCall::Add { parent: 0, node });
+        assert!(save(&mut store, instance, HASH).is_ok());
+
+        let state = store.data_mut();
+        state.canvas = None;
+        state.remap = None;
+        state.clip = Some(Rectangle::new(Point::zero(), Size::new(1, 1)));
+        state.frame.set_clip(state.clip);
+        state.offset = Point::zero();
+        state.fill_pattern = None;
+        state.n_updates = 0;
+        state.uptime = 0;
+        state.audio.clear(0).ok().unwrap();
+        assert!(state.audio.get_node(id).is_err());
+        assert!(load(&mut store, instance, HASH).is_ok());
+
+        let state = store.data_mut();
+        assert_eq!(
+            state.canvas.as_ref().map(Canvas::raw),
+            Some((2000, 800, 40))
+        );
+        assert_eq!(state.remap, Some([3; 16]));
+        let clip = Rectangle::new(Point::new(-4, 5), Size::new(30, 20));
+        assert_eq!(state.clip, Some(clip));
+        // The frame clip is limited to the screen.
+        let frame_clip = Rectangle::new(Point::new(0, 5), Size::new(26, 20));
+        assert_eq!(state.frame.clip(), frame_clip);
+        assert_eq!(state.offset, Point::new(7, -8));
+        let pattern = state.fill_pattern.unwrap();
+        assert_eq!(pattern.mask(), Pattern::checkerboard(Gray4::new(9)).mask());
+        assert_eq!(pattern.color(), Gray4::new(9));
+        assert_eq!(state.n_updates, 90);
+        assert_eq!(state.uptime, 1_500_000_000);
+        assert!(state.audio.get_node(id).is_ok());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// A canvas outside of the snapshot memory is rejected.
+    #[test]
+    fn test_load_canvas_out_of_memory() {
+        let (root, mut store, instance) = make_store("load-canvas-oom");
+        let size = store.data().memory.unwrap().data(&store).len() as u32;
+        store.data_mut().canvas = Some(Canvas::new(size - 10, 20, 4));
+        assert!(save(&mut store, instance, HASH).is_ok());
+        store.data_mut().canvas = None;
+        let res = load(&mut store, instance, HASH);
+        assert!(matches!(res, Err(SnapshotError::Invalid)));
+        assert!(store.data().canvas.is_none());
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// Loading a corrupted snapshot must not change the app.
+    #[test]
+    fn test_load_corrupted() {
+        let (root, mut store, instance) = make_store("load-corrupted");
+        set_app_state(&mut store, instance, 13);
+        assert!(save(&mut store, instance, HASH).is_ok());
+        set_app_state(&mut store, instance, 0);
+
+        let path = root.join("data/test-author/test-app/snapshot");
+        let raw = std::fs::read(&path).unwrap();
+        // Truncated in the middle of the memory.
+        std::fs::write(&path, &raw[..raw.len() - 100]).unwrap();
+        let res = load(&mut store, instance, HASH);
+        assert!(matches!(res, Err(SnapshotError::Invalid)));
+        check_app_state(&store, instance, 0);
+
+        // Invalid magic number.
+        let mut bad = raw.clone();
+        bad[0] = 0;
+        std::fs::write(&path, &bad).unwrap();
+        let res = load(&mut store, instance, HASH);
+        assert!(matches!(res, Err(SnapshotError::Invalid)));
+        check_app_state(&store, instance, 0);
+
+        // Trailing garbage.
+        let mut bad = raw;
+        bad.push(0);
+        std::fs::write(&path, &bad).unwrap();
+        let res = load(&mut store, instance, HASH);
+        assert!(matches!(res, Err(SnapshotError::Invalid)));
+        check_app_state(&store, instance, 0);
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// Set the seed, frame, memory, and the global to values derived from `n`.
+    fn set_app_state(store: &mut Store<'static>, instance: wasmi::Instance, n: u8) {
+        let global = instance.get_global(&*store, "counter").unwrap();
+        global.set(&mut *store, Val::I32(i32::from(n))).unwrap();
+        let memory = store.data().memory.unwrap();
+        memory.data_mut(&mut *store)[1000] = n;
+        let state = store.data_mut();
+        state.seed = u32::from(n);
+        state.frame.palette[2] = Rgb16(n, n);
+        state.frame.set_pixel(Point::new(1, 1), n % 16);
+    }
+
+    fn check_app_state(store: &Store<'static>, instance: wasmi::Instance, n: u8) {
+        let global = instance.get_global(store, "counter").unwrap();
+        assert!(matches!(global.get(store), Val::I32(v) if v == i32::from(n)));
+        let memory = store.data().memory.unwrap();
+        assert_eq!(memory.data(store)[1000], n);
+        let state = store.data();
+        assert_eq!(state.seed, u32::from(n));
+        assert!(state.frame.palette[2] == Rgb16(n, n));
+        assert_eq!(state.frame.get_pixel(1, 1), Some(n % 16));
+    }
+
+    fn make_store(name: &str) -> (PathBuf, Store<'static>, wasmi::Instance) {
+        let root = std::env::temp_dir()
+            .join("firefly-snapshot-test")
+            .join(name);
+        _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sys")).unwrap();
+        std::fs::create_dir_all(root.join("data/test-author/test-app")).unwrap();
+        let config = DeviceConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+        let mut device = DeviceImpl::new(config);
+        let rom_dir = device.open_dir(&["sys"]).ok().unwrap();
+        let id = FullID::from_str("test-author", "test-app").unwrap();
+        let state = State::new(id, device, rom_dir, NetHandler::None, false);
+
+        let engine = wasmi::Engine::default();
+        let mut store = wasmi::Store::new(&engine, state);
+        let wasm = Module::default().memory(1).global("counter", 0).encode();
+        let module = wasmi::Module::new(&engine, &wasm[..]).unwrap();
+        let instance = wasmi::Instance::new(&mut store, &module, &[]).unwrap();
+        let memory = instance.get_memory(&store, "memory");
+        store.data_mut().memory = memory;
+        (root, store, instance)
+    }
+}
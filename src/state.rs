@@ -1,21 +1,31 @@
 use crate::Error;
+use crate::audio_log::AudioLog;
 use crate::battery::Battery;
 use crate::canvas::Canvas;
 use crate::color::Rgb16;
 use crate::config::FullID;
 use crate::error::RuntimeStats;
 use crate::frame_buffer::FrameBuffer;
+use crate::image::Remap;
 use crate::menu::{Menu, MenuItem};
 use crate::net::*;
+use crate::pattern::Pattern;
+use crate::replay::{Player, Recorder, Replay, ReplayFrame};
+use crate::snapshot::SnapshotAction;
 use crate::utils::{copy_stream, read_all, read_all_into};
 use alloc::boxed::Box;
 use core::cell::Cell;
 use core::fmt::Display;
 use core::str::FromStr;
 use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics::prelude::Point;
+use embedded_graphics::primitives::Rectangle;
 use embedded_io::Write;
 use firefly_hal::*;
-use firefly_types::Encode;
+use firefly_types::{Encode, serial};
+
+/// Frames per second if the app doesn't change it.
+pub(crate) const DEFAULT_FPS: u8 = 60;
 
 #[allow(private_interfaces)]
 pub enum NetHandler {
@@ -39,6 +49,9 @@ pub(crate) struct State<'a> {
     /// Audio manager.
     pub audio: firefly_audio::Manager,
 
+    /// The calls that built the audio graph, used by save states.
+    pub audio_log: AudioLog,
+
     /// The id of the currently running app.
     pub id: FullID,
 
@@ -48,6 +61,18 @@ pub(crate) struct State<'a> {
     /// An image in the guest memory that, if not None, used to graphics as draw target.
     pub canvas: Option<Canvas>,
 
+    /// If set, the colors of images and text are replaced when drawing.
+    pub remap: Option<Remap>,
+
+    /// If set, nothing is drawn outside of this area of the draw target.
+    pub clip: Option<Rectangle>,
+
+    /// Added to the coordinates of everything that is drawn.
+    pub offset: Point,
+
+    /// If set, the fill of shapes is drawn using this pattern.
+    pub fill_pattern: Option<Pattern>,
+
     /// The current state of the randomization function.
     pub seed: u32,
 
@@ -69,6 +94,23 @@ pub(crate) struct State<'a> {
     /// The last read touch pad and buttons input of the current device.
     pub input: Option<InputState>,
 
+    /// If true, the input is not read from the device.
+    ///
+    /// Instead, [`State::headless_input`] is used, set by the test harness.
+    pub headless: bool,
+
+    /// The input injected by the test harness, used in the headless mode.
+    ///
+    /// The input is already in the app coordinates, so the screen rotation
+    /// is not applied to it.
+    pub headless_input: Option<InputState>,
+
+    /// Buttons pressed remotely, sent over the USB serial port.
+    ///
+    /// Merged into the device input on every update
+    /// until the next serial request changes them.
+    pub remote_buttons: u8,
+
     /// The last called host function.
     pub called: &'static str,
 
@@ -79,11 +121,34 @@ pub(crate) struct State<'a> {
     pub battery: Option<Battery>,
 
     pub app_stats: Option<firefly_types::Stats>,
-    /// The number of update frames.
-    n_frames: u32,
+    /// How long the app is running, in 1/60 of a second.
+    ///
+    /// Doesn't depend on the app frame rate.
+    n_ticks: u32,
+    /// How many times per second `update` is called, set by the app.
+    pub fps: u8,
+    /// How many times the app `update` callback was called.
+    pub n_updates: u32,
+    /// Monotonic time since the app start at the beginning of the current frame,
+    /// in nanoseconds.
+    pub uptime: u64,
+    /// The device time when [`State::uptime`] was updated.
+    prev_now: Instant,
     pub stash: alloc::vec::Vec<u8>,
     pub stash_dirty: bool,
 
+    /// The save state action selected in the system menu but not yet applied.
+    pub snapshot: Option<SnapshotAction>,
+
+    /// If set, the input is either recorded into or replayed from the replay file.
+    pub replay: Option<Replay>,
+
+    /// The last data payload received from the USB serial port.
+    ///
+    /// The app is notified about it by the `handle_data` callback
+    /// and can read it using `misc.load_data`.
+    pub serial_data: alloc::vec::Vec<u8>,
+
     pub net_handler: Cell<NetHandler>,
     action: Action,
 }
@@ -111,6 +176,7 @@ impl<'a> State<'a> {
             }
         }
 
+        let offline = matches!(net_handler, NetHandler::None);
         let seed = match &net_handler {
             NetHandler::FrameSyncer(syncer) => syncer.shared_seed,
             _ => 0,
@@ -118,15 +184,21 @@ impl<'a> State<'a> {
         let mut device = device;
         let maybe_battery = Battery::new(&mut device);
         let settings = load_settings(&mut device).unwrap_or_default();
+        let now = device.now();
         Box::new(Self {
             device,
             rom_dir,
             id,
             frame: FrameBuffer::new(),
             canvas: None,
-            menu: Menu::new(),
+            remap: None,
+            clip: None,
+            offset: Point::zero(),
+            fill_pattern: None,
+            menu: Menu::new(offline),
             launcher,
             audio: firefly_audio::Manager::new(),
+            audio_log: AudioLog::default(),
             battery: maybe_battery.ok(),
             seed,
             lock_seed: false,
@@ -134,13 +206,23 @@ impl<'a> State<'a> {
             next: None,
             exit: false,
             input: None,
+            headless: false,
+            headless_input: None,
+            remote_buttons: 0,
             called: "",
             net_handler: Cell::new(net_handler),
             settings,
             app_stats: None,
-            n_frames: 0,
+            n_ticks: 0,
+            fps: DEFAULT_FPS,
+            n_updates: 0,
+            uptime: 0,
+            prev_now: now,
             stash: alloc::vec::Vec::new(),
             stash_dirty: false,
+            serial_data: alloc::vec::Vec::new(),
+            replay: None,
+            snapshot: None,
             action: Action::None,
         })
     }
@@ -269,7 +351,7 @@ impl<'a> State<'a> {
             return;
         };
         stats.launches[idx] += 1;
-        let minutes = self.n_frames / 3600;
+        let minutes = self.n_ticks / (60 * 60);
         stats.minutes[idx] += minutes;
         if minutes > stats.longest_play[idx] {
             stats.longest_play[idx] = minutes;
@@ -322,16 +404,17 @@ impl<'a> State<'a> {
 
     /// Update the state: read inputs, handle system commands.
     pub(crate) fn update(&mut self) -> Option<u8> {
-        self.n_frames += 1;
+        self.n_ticks += u32::from(DEFAULT_FPS / self.fps);
+        self.update_uptime();
         {
-            let mut input = self.device.read_input();
-            if self.settings.rotate_screen
-                && let Some(input) = input.as_mut()
-            {
-                input.rotate();
-            }
-            self.input = input;
+            let (input, rotate) = if self.headless {
+                (self.headless_input.clone(), false)
+            } else {
+                (self.device.read_input(), self.settings.rotate_screen)
+            };
+            self.input = merge_input(input, rotate, self.remote_buttons);
         }
+        self.update_replay();
         self.update_net();
 
         // Get combined input for all peers.
@@ -378,12 +461,16 @@ impl<'a> State<'a> {
             }
         };
 
-        if !self.launcher {
+        // The launcher doesn't have the app menu,
+        // but it still must be possible to leave the crash report.
+        if !self.launcher || self.menu.is_crashed() {
             let action = self.menu.handle_input(&input);
             if let Some(action) = action {
                 match action {
                     MenuItem::Custom(index, _) => return Some(*index),
                     MenuItem::ScreenShot => self.take_screenshot(),
+                    MenuItem::SaveState => self.snapshot = Some(SnapshotAction::Save),
+                    MenuItem::LoadState => self.snapshot = Some(SnapshotAction::Load),
                     MenuItem::Restart => self.set_next(Some(self.id.clone())),
                     MenuItem::Quit => self.set_next(None),
                 };
@@ -392,6 +479,109 @@ impl<'a> State<'a> {
         None
     }
 
+    /// Advance the monotonic clock exposed to the app.
+    ///
+    /// In the headless mode, the clock is virtual and each frame takes exactly 1/fps second.
+    fn update_uptime(&mut self) {
+        if self.headless {
+            self.uptime += 1_000_000_000 / u64::from(self.fps);
+            return;
+        }
+        let now = self.device.now();
+        self.uptime += u64::from((now - self.prev_now).ns());
+        self.prev_now = now;
+    }
+
+    /// The precise monotonic time since the app start, in nanoseconds.
+    ///
+    /// Unlike [`State::uptime`], includes the time passed since the frame start.
+    /// The exception is the headless mode and replays where the time must be
+    /// deterministic, so it stays the same during the whole frame.
+    pub(crate) fn now_ns(&self) -> u64 {
+        if self.headless || self.replay.is_some() {
+            return self.uptime;
+        }
+        let now = self.device.now();
+        self.uptime + u64::from((now - self.prev_now).ns())
+    }
+
+    /// Start recording the input, seeds, and actions into the replay file.
+    pub(crate) fn start_recording(&mut self) -> Result<(), Error> {
+        // The true RNG cannot be replayed, so we make sure
+        // that the app uses the seeded PRNG instead.
+        if self.seed == 0 {
+            self.seed = self.device.random();
+        }
+        let recorder = match Recorder::new(&mut self.device, &self.id, self.seed) {
+            Ok(recorder) => recorder,
+            Err(err) => return Err(Error::OpenFile("replay", err)),
+        };
+        self.lock_seed = true;
+        self.replay = Some(Replay::Record(recorder));
+        Ok(())
+    }
+
+    /// Start feeding the input, seeds, and actions from the replay file.
+    pub(crate) fn start_replay(&mut self) -> Result<(), Error> {
+        let player = match Player::new(&mut self.device, &self.id) {
+            Ok(Some(player)) => player,
+            Ok(None) => return Err(Error::InvalidReplay),
+            Err(err) => return Err(Error::ReadFile("replay", err)),
+        };
+        self.seed = player.seed;
+        self.lock_seed = true;
+        self.replay = Some(Replay::Play(player));
+        Ok(())
+    }
+
+    /// Record the current frame or replace it with the recorded one.
+    fn update_replay(&mut self) {
+        let frame = match &mut self.replay {
+            None => return,
+            Some(Replay::Record(recorder)) => {
+                let frame = ReplayFrame {
+                    input: self.input.clone(),
+                    seed: self.seed,
+                    action: self.action,
+                    uptime: self.uptime,
+                };
+                recorder.push(&frame);
+                if recorder.should_flush() {
+                    let res = recorder.flush(&mut self.device, &self.id);
+                    if let Err(err) = res {
+                        self.device.log_error("replay", err);
+                    }
+                }
+                return;
+            }
+            Some(Replay::Play(player)) => player.next_frame(),
+        };
+        let Some(frame) = frame else {
+            self.device
+                .log_debug("replay", "all recorded frames are played");
+            self.replay = None;
+            return;
+        };
+        self.input = frame.input;
+        self.seed = frame.seed;
+        self.uptime = frame.uptime;
+        match frame.action {
+            Action::None => {}
+            Action::Restart => self.set_next(Some(self.id.clone())),
+            Action::Exit => self.set_next(None),
+        }
+    }
+
+    /// Write on disk all recorded but not yet saved frames.
+    pub(crate) fn save_replay(&mut self) {
+        if let Some(Replay::Record(recorder)) = &mut self.replay {
+            let res = recorder.flush(&mut self.device, &self.id);
+            if let Err(err) = res {
+                self.device.log_error("replay", err);
+            }
+        }
+    }
+
     fn update_net(&mut self) {
         let handler = self.net_handler.replace(NetHandler::None);
         let handler = match handler {
@@ -556,6 +746,19 @@ impl<'a> State<'a> {
         }
     }
 
+    /// Send a response into the USB serial port.
+    pub(crate) fn serial_send(&mut self, resp: serial::Response) -> Result<(), Error> {
+        let encoded = match resp.encode_vec() {
+            Ok(encoded) => encoded,
+            Err(err) => return Err(Error::SerialEncode(err)),
+        };
+        let res = self.device.serial_send(&encoded);
+        if let Err(err) = res {
+            return Err(Error::SerialSend(err));
+        }
+        Ok(())
+    }
+
     /// Log an error/warning occurred in the currently executing host function.
     pub fn log_error<D: Display>(&mut self, msg: D) {
         self.device.log_error(self.called, &msg);
@@ -642,3 +845,44 @@ fn encode_palette(palette: &[Rgb16; 16]) -> [u8; 16 * 3] {
     }
     encoded
 }
+
+/// Apply the screen rotation to the device input and merge the remote buttons into it.
+///
+/// Remote buttons are already in the app coordinates,
+/// so they must be merged after the screen rotation is applied.
+fn merge_input(mut input: Option<InputState>, rotate: bool, remote: u8) -> Option<InputState> {
+    if rotate && let Some(input) = input.as_mut() {
+        input.rotate();
+    }
+    if remote != 0 {
+        let input = input.get_or_insert_with(InputState::default);
+        input.buttons |= remote;
+    }
+    input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_input() {
+        assert!(merge_input(None, true, 0).is_none());
+        let input = merge_input(None, true, 0b100).unwrap();
+        assert!(input.pad.is_none());
+        assert_eq!(input.buttons, 0b100);
+
+        let local = InputState {
+            pad: Some((100_i16, -200_i16).into()),
+            buttons: 0b11,
+        };
+        let mut rotated = local.clone();
+        rotated.rotate();
+        let input = merge_input(Some(local), true, 0b100).unwrap();
+        // The rotation is applied only to the local input.
+        let pad: (i16, i16) = input.pad.unwrap().into();
+        let expected: (i16, i16) = rotated.pad.unwrap().into();
+        assert_eq!(pad, expected);
+        assert_eq!(input.buttons, rotated.buttons | 0b100);
+    }
+}